@@ -0,0 +1,85 @@
+//! A small radix-2 FFT, enough for the frame sizes the analysers use.
+
+use std::f32::consts::PI;
+
+/// Magnitude spectra of fixed, power of two sized frames.
+pub struct Fft {
+    size: usize,
+    /// `exp(-2πik/size)` for `k < size / 2`.
+    twiddles: Vec<(f32, f32)>,
+    bit_reversed: Vec<usize>,
+    window: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    magnitudes: Vec<f32>,
+}
+
+impl Fft {
+    pub fn new(size: usize) -> Fft {
+        assert!(
+            size >= 2 && size.is_power_of_two(),
+            "fft size must be a power of two"
+        );
+        let bits = size.trailing_zeros();
+        Fft {
+            size,
+            twiddles: (0..size / 2)
+                .map(|k| {
+                    let angle = -2.0 * PI * k as f32 / size as f32;
+                    (angle.cos(), angle.sin())
+                })
+                .collect(),
+            bit_reversed: (0..size)
+                .map(|i| i.reverse_bits() >> (usize::BITS - bits))
+                .collect(),
+            window: (0..size)
+                .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+                .collect(),
+            re: vec![0.0; size],
+            im: vec![0.0; size],
+            magnitudes: vec![0.0; size / 2 + 1],
+        }
+    }
+
+    /// The `size / 2 + 1` bin magnitudes of a Hann windowed `frame`.
+    pub fn magnitudes(&mut self, frame: &[f32]) -> &[f32] {
+        for i in 0..self.size {
+            self.re[i] = frame.get(i).copied().unwrap_or(0.0) * self.window[i];
+            self.im[i] = 0.0;
+        }
+        self.transform();
+        for (k, m) in self.magnitudes.iter_mut().enumerate() {
+            *m = (self.re[k] * self.re[k] + self.im[k] * self.im[k]).sqrt();
+        }
+        &self.magnitudes
+    }
+
+    fn transform(&mut self) {
+        let n = self.size;
+        for i in 0..n {
+            let j = self.bit_reversed[i];
+            if j > i {
+                self.re.swap(i, j);
+                self.im.swap(i, j);
+            }
+        }
+        let mut len = 2;
+        while len <= n {
+            let half = len / 2;
+            let stride = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..half {
+                    let (wr, wi) = self.twiddles[k * stride];
+                    let (a, b) = (start + k, start + k + half);
+                    let tr = self.re[b] * wr - self.im[b] * wi;
+                    let ti = self.re[b] * wi + self.im[b] * wr;
+                    self.re[b] = self.re[a] - tr;
+                    self.im[b] = self.im[a] - ti;
+                    self.re[a] += tr;
+                    self.im[a] += ti;
+                }
+            }
+            len *= 2;
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Key and scale estimation: a chroma vector summed over the whole file,
+//! correlated against the Krumhansl-Kessler key profiles.
+
+use super::fft::Fft;
+use std::fmt;
+use std::path::Path;
+
+const FRAME: usize = 8192;
+const HOP: usize = 4096;
+/// Partials outside this range say little about the key.
+const MIN_FREQUENCY: f32 = 100.0;
+const MAX_FREQUENCY: f32 = 4000.0;
+
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const MAJOR_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+const MINOR_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    /// Pitch class of the tonic, C = 0.
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            Mode::Major => f.write_str(MAJOR_NAMES[self.tonic as usize]),
+            Mode::Minor => write!(f, "{}m", MINOR_NAMES[self.tonic as usize]),
+        }
+    }
+}
+
+impl Key {
+    /// Position on the Camelot wheel: `(1..=12, 'A')` for minor keys and
+    /// `'B'` for major ones, so C major is 8B and A minor 8A.
+    pub fn camelot(self) -> (u8, char) {
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+        let number = (major_tonic * 7 % 12 + 7) % 12 + 1;
+        let letter = match self.mode {
+            Mode::Major => 'B',
+            Mode::Minor => 'A',
+        };
+        (number, letter)
+    }
+
+    fn from_camelot(number: u8, letter: char) -> Option<Key> {
+        if !(1..=12).contains(&number) {
+            return None;
+        }
+        // 7 is its own inverse mod 12, so this undoes the mapping above
+        let major_tonic = (number + 4) % 12 * 7 % 12;
+        match letter.to_ascii_uppercase() {
+            'B' => Some(Key {
+                tonic: major_tonic,
+                mode: Mode::Major,
+            }),
+            'A' => Some(Key {
+                tonic: (major_tonic + 9) % 12,
+                mode: Mode::Minor,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Keys that mix well with this one: itself, its relative major or
+    /// minor, and its neighbours on the Camelot wheel.
+    pub fn is_compatible(self, other: Key) -> bool {
+        let ((a, a_letter), (b, b_letter)) = (self.camelot(), other.camelot());
+        let distance = (a as i8 - b as i8).rem_euclid(12);
+        if a_letter == b_letter {
+            matches!(distance, 0 | 1 | 11)
+        } else {
+            distance == 0
+        }
+    }
+
+    /// Parses `Am`, `A minor`, `C#min`, `Dbmaj`, `F#`, `8A` and the like,
+    /// ignoring case.
+    pub fn parse(s: &str) -> Option<Key> {
+        let s = s.trim();
+        if let Some(letter) = s.chars().last().filter(|c| c.is_ascii_alphabetic())
+            && let Ok(number) = s[..s.len() - 1].parse::<u8>()
+        {
+            return Key::from_camelot(number, letter);
+        }
+        let (tonic, rest) = tonic(s, false)?;
+        let mode = match rest.trim().to_lowercase().as_str() {
+            "" | "maj" | "major" => Mode::Major,
+            "m" | "min" | "minor" => Mode::Minor,
+            _ => return None,
+        };
+        Some(Key { tonic, mode })
+    }
+
+    /// Like `parse`, but only for the tokens a file name would spell a key
+    /// with: the tonic must be a capital letter and a lone letter does not
+    /// count, since names like `Kick_A` are far more often variations.
+    fn parse_token(token: &str) -> Option<Key> {
+        let (tonic, rest) = tonic(token, true)?;
+        let has_accidental = rest.len() < token.len() - 1;
+        let mode = match rest {
+            "m" | "min" | "Min" | "MIN" | "minor" | "Minor" => Mode::Minor,
+            "maj" | "Maj" | "MAJ" | "major" | "Major" => Mode::Major,
+            "" if has_accidental => Mode::Major,
+            _ => return None,
+        };
+        Some(Key { tonic, mode })
+    }
+}
+
+/// Splits off a leading note name and its accidental.
+fn tonic(s: &str, capital: bool) -> Option<(u8, &str)> {
+    let mut chars = s.chars();
+    let letter = chars.next()?;
+    if capital && !letter.is_ascii_uppercase() {
+        return None;
+    }
+    let natural: u8 = match letter.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (tonic, rest) = if let Some(rest) = rest.strip_prefix(['#', '♯']) {
+        (natural + 1, rest)
+    } else if let Some(rest) = rest.strip_prefix(['b', '♭']) {
+        (natural + 11, rest)
+    } else {
+        (natural, rest)
+    };
+    Some((tonic % 12, rest))
+}
+
+/// The first key spelled out in a file name, e.g. `Pad_Am_90.wav` or
+/// `lead C#min.wav`.
+pub fn from_name(path: &Path) -> Option<Key> {
+    let name = path.file_stem()?.to_string_lossy();
+    name.split(|c: char| !(c.is_alphanumeric() || c == '#' || c == '♯' || c == '♭'))
+        .find_map(Key::parse_token)
+}
+
+/// Sums the energy of every analysed frame per pitch class.
+pub struct Chroma {
+    fft: Fft,
+    frame: Vec<f32>,
+    frames: usize,
+    /// Pitch class of each fft bin, if it is in the useful range.
+    pitch_classes: Vec<Option<usize>>,
+    chroma: [f64; 12],
+}
+
+impl Chroma {
+    pub fn new(sample_rate: u32) -> Chroma {
+        let bin_width = sample_rate as f32 / FRAME as f32;
+        let pitch_classes = (0..=FRAME / 2)
+            .map(|bin| {
+                let frequency = bin as f32 * bin_width;
+                if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+                    return None;
+                }
+                // semitones above C, with A4 at 440Hz
+                let semitone = (12.0 * (frequency / 440.0).log2()).round() as i32 + 9;
+                Some(semitone.rem_euclid(12) as usize)
+            })
+            .collect();
+        Chroma {
+            fft: Fft::new(FRAME),
+            frame: Vec::with_capacity(FRAME),
+            frames: 0,
+            pitch_classes,
+            chroma: [0.0; 12],
+        }
+    }
+
+    /// Takes one sample of the mono downmix.
+    pub fn push(&mut self, x: f32) {
+        self.frame.push(x);
+        if self.frame.len() == FRAME {
+            self.analyze_frame();
+            self.frame.drain(..HOP);
+        }
+    }
+
+    fn analyze_frame(&mut self) {
+        let magnitudes = self.fft.magnitudes(&self.frame);
+        for (m, pitch_class) in magnitudes.iter().zip(&self.pitch_classes) {
+            if let Some(pc) = pitch_class {
+                self.chroma[*pc] += *m as f64;
+            }
+        }
+        self.frames += 1;
+    }
+
+    /// The best matching key, or `None` for noise and unpitched sounds.
+    pub fn finish(mut self) -> Option<Key> {
+        // one-shots shorter than a frame still get a (zero padded) look
+        if self.frames == 0 && !self.frame.is_empty() {
+            self.analyze_frame();
+        }
+        let mean = self.chroma.iter().sum::<f64>() / 12.0;
+        let max = self.chroma.iter().copied().fold(0.0, f64::max);
+        // a flat chroma means there is no pitch to speak of
+        if mean <= 0.0 || max / mean < 1.5 {
+            return None;
+        }
+        let (key, correlation) = (0..12u8)
+            .flat_map(|tonic| {
+                [
+                    Key {
+                        tonic,
+                        mode: Mode::Major,
+                    },
+                    Key {
+                        tonic,
+                        mode: Mode::Minor,
+                    },
+                ]
+            })
+            .map(|key| {
+                let profile = match key.mode {
+                    Mode::Major => &MAJOR_PROFILE,
+                    Mode::Minor => &MINOR_PROFILE,
+                };
+                let rotated: Vec<f64> = (0..12)
+                    .map(|pc| profile[(pc + 12 - key.tonic as usize) % 12])
+                    .collect();
+                (key, pearson(&self.chroma, &rotated))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        (correlation > 0.5).then_some(key)
+    }
+}
+
+fn pearson(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a <= 0.0 || var_b <= 0.0 {
+        0.0
+    } else {
+        cov / (var_a * var_b).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(tonic: u8, mode: Mode) -> Option<Key> {
+        Some(Key { tonic, mode })
+    }
+
+    fn named(name: &str) -> Option<Key> {
+        from_name(Path::new(name))
+    }
+
+    #[test]
+    fn parses_key_names_and_camelot() {
+        assert_eq!(Key::parse("Am"), key(9, Mode::Minor));
+        assert_eq!(Key::parse("a minor"), key(9, Mode::Minor));
+        assert_eq!(Key::parse("C#min"), key(1, Mode::Minor));
+        assert_eq!(Key::parse("Dbmaj"), key(1, Mode::Major));
+        assert_eq!(Key::parse("F#"), key(6, Mode::Major));
+        assert_eq!(Key::parse("8A"), key(9, Mode::Minor));
+        assert_eq!(Key::parse("8b"), key(0, Mode::Major));
+        assert_eq!(Key::parse("13A"), None);
+        assert_eq!(Key::parse("H"), None);
+    }
+
+    #[test]
+    fn reads_keys_from_names() {
+        assert_eq!(named("Pad_Am_90.wav"), key(9, Mode::Minor));
+        assert_eq!(named("lead C#min.wav"), key(1, Mode::Minor));
+        assert_eq!(named("Bass-Ebmaj-120bpm.wav"), key(3, Mode::Major));
+        assert_eq!(named("Chord_F♯.wav"), key(6, Mode::Major));
+        assert_eq!(named("Stab Bm Dm.wav"), key(11, Mode::Minor));
+    }
+
+    #[test]
+    fn ignores_words_and_lone_letters_in_names() {
+        // lone letters are usually variations, lowercase ones just text
+        assert_eq!(named("Kick_A.wav"), None);
+        assert_eq!(named("hat_am_open.wav"), None);
+        assert_eq!(named("Drum Loop.wav"), None);
+        assert_eq!(named("Amen Break.wav"), None);
+    }
+
+    #[test]
+    fn relative_keys_and_neighbours_are_compatible() {
+        let a_minor = Key::parse("Am").unwrap();
+        for compatible in ["Am", "C", "Em", "Dm"] {
+            assert!(
+                a_minor.is_compatible(Key::parse(compatible).unwrap()),
+                "{}",
+                compatible
+            );
+        }
+        for clashing in ["G", "Bm", "F#m", "A"] {
+            assert!(
+                !a_minor.is_compatible(Key::parse(clashing).unwrap()),
+                "{}",
+                clashing
+            );
+        }
+    }
+}
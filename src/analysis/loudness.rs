@@ -0,0 +1,260 @@
+//! ITU-R BS.1770 integrated loudness and true peak, computed on the fly so
+//! long files never have to be held in memory.
+
+/// Level reported for digital silence, where the log would be -inf.
+pub const SILENCE_DB: f64 = -150.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// The most loudness matching boosts a quiet file by, in dB.
+const MAX_BOOST: f64 = 12.0;
+/// Loudness matching does not boost peaks past this, in dBTP.
+const PEAK_CEILING: f64 = -1.0;
+
+pub fn to_db(amplitude: f64) -> f64 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+/// The gain in dB that brings a file measured at `lufs` to `target`, short
+/// of boosting its `peak` into clipping.
+pub fn matching_gain(lufs: f64, peak: f64, target: f64) -> f64 {
+    let gain = target - lufs;
+    if gain <= 0.0 {
+        gain
+    } else {
+        gain.min(MAX_BOOST).min((PEAK_CEILING - peak).max(0.0))
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    if energy > 0.0 {
+        (-0.691 + 10.0 * energy.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// The two stage K-weighting filter, derived for any sample rate the same
+/// way libebur128 does rather than using the 48kHz table from the spec.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Biquad::default()
+    };
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Biquad::default()
+    };
+    [shelf, high_pass]
+}
+
+/// Channel weights for the usual layouts; surrounds of a 5.1 stream count
+/// more and the LFE channel is ignored.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Gated integrated loudness. Energy is collected in 100ms steps and the
+/// 400ms gating blocks are assembled from four consecutive steps, which
+/// gives the 75% overlap the spec asks for.
+pub struct Loudness {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    step_len: usize,
+    step_pos: usize,
+    step_energy: f64,
+    steps: Vec<f64>,
+    total_energy: f64,
+    frames: usize,
+}
+
+impl Loudness {
+    pub fn new(channels: usize, sample_rate: u32) -> Loudness {
+        Loudness {
+            filters: vec![k_weighting(sample_rate as f64); channels],
+            weights: (0..channels).map(|c| channel_weight(c, channels)).collect(),
+            step_len: (sample_rate as usize / 10).max(1),
+            step_pos: 0,
+            step_energy: 0.0,
+            steps: Vec::new(),
+            total_energy: 0.0,
+            frames: 0,
+        }
+    }
+
+    pub fn push(&mut self, frame: &[f32]) {
+        let mut energy = 0.0;
+        for ((&x, filter), weight) in frame.iter().zip(&mut self.filters).zip(&self.weights) {
+            let [shelf, high_pass] = filter;
+            let y = high_pass.process(shelf.process(x as f64));
+            energy += weight * y * y;
+        }
+        self.step_energy += energy;
+        self.total_energy += energy;
+        self.frames += 1;
+        self.step_pos += 1;
+        if self.step_pos == self.step_len {
+            self.steps.push(self.step_energy / self.step_len as f64);
+            self.step_energy = 0.0;
+            self.step_pos = 0;
+        }
+    }
+
+    /// Integrated loudness in LUFS. Files shorter than one gating block are
+    /// measured ungated over their whole length.
+    pub fn finish(&self) -> f64 {
+        if self.steps.len() < 4 {
+            return energy_to_lufs(self.total_energy / self.frames.max(1) as f64);
+        }
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(4)
+            .map(|w| w.iter().sum::<f64>() / 4.0)
+            .filter(|&z| energy_to_lufs(z) > ABSOLUTE_GATE)
+            .collect();
+        if blocks.is_empty() {
+            return SILENCE_DB;
+        }
+        let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+            let (sum, n) = blocks.fold((0.0, 0usize), |(s, n), z| (s + z, n + 1));
+            if n == 0 { 0.0 } else { sum / n as f64 }
+        };
+        let relative_gate = energy_to_lufs(mean(&mut blocks.iter().copied())) + RELATIVE_GATE;
+        energy_to_lufs(mean(
+            &mut blocks
+                .iter()
+                .copied()
+                .filter(|&z| energy_to_lufs(z) > relative_gate),
+        ))
+    }
+}
+
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Peak level after 4x oversampling, which catches the inter-sample peaks a
+/// DAC will reconstruct (BS.1770 annex 2).
+pub struct TruePeak {
+    phases: [[f64; TAPS_PER_PHASE]; OVERSAMPLING],
+    history: Vec<[f64; TAPS_PER_PHASE]>,
+    pos: usize,
+    peak: f64,
+}
+
+impl TruePeak {
+    pub fn new(channels: usize) -> TruePeak {
+        // windowed sinc, split into one polyphase branch per output phase
+        let taps = OVERSAMPLING * TAPS_PER_PHASE;
+        let center = (taps - 1) as f64 / 2.0;
+        let mut phases = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+        for i in 0..taps {
+            let t = (i as f64 - center) / OVERSAMPLING as f64;
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                (std::f64::consts::PI * t).sin() / (std::f64::consts::PI * t)
+            };
+            let window =
+                0.5 - 0.5 * (2.0 * std::f64::consts::PI * (i as f64 + 0.5) / taps as f64).cos();
+            phases[i % OVERSAMPLING][i / OVERSAMPLING] = sinc * window;
+        }
+        for phase in &mut phases {
+            let gain: f64 = phase.iter().sum();
+            phase.iter_mut().for_each(|h| *h /= gain);
+        }
+        TruePeak {
+            phases,
+            history: vec![[0.0; TAPS_PER_PHASE]; channels],
+            pos: 0,
+            peak: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, frame: &[f32]) {
+        for (&x, history) in frame.iter().zip(&mut self.history) {
+            let x = x as f64;
+            self.peak = self.peak.max(x.abs());
+            history[self.pos] = x;
+            for phase in &self.phases {
+                // history is a ring buffer, newest sample at `pos`
+                let mut y = 0.0;
+                for (k, h) in phase.iter().enumerate() {
+                    y += h * history[(self.pos + TAPS_PER_PHASE - k) % TAPS_PER_PHASE];
+                }
+                self.peak = self.peak.max(y.abs());
+            }
+        }
+        self.pos = (self.pos + 1) % TAPS_PER_PHASE;
+    }
+
+    /// Linear peak; 1.0 is full scale.
+    pub fn finish(&self) -> f64 {
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_loud_files_down_all_the_way() {
+        assert_eq!(matching_gain(-8.0, 0.0, -14.0), -6.0);
+        // even ones peaking over full scale
+        assert_eq!(matching_gain(-6.0, 2.0, -14.0), -8.0);
+    }
+
+    #[test]
+    fn boosts_quiet_files_within_limits() {
+        assert_eq!(matching_gain(-20.0, -10.0, -14.0), 6.0);
+        assert_eq!(matching_gain(-40.0, -30.0, -14.0), MAX_BOOST);
+        // stops short of pushing the peak past the ceiling
+        assert_eq!(matching_gain(-20.0, -3.0, -14.0), 2.0);
+        assert_eq!(matching_gain(-20.0, 0.5, -14.0), 0.0);
+    }
+}
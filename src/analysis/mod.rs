@@ -0,0 +1,120 @@
+//! Offline measurements of a whole file, taken in a single decoding pass.
+
+pub mod fft;
+pub mod key;
+pub mod loudness;
+pub mod riff;
+pub mod tempo;
+pub mod timbre;
+
+use key::{Chroma, Key};
+use loudness::{Loudness, TruePeak, to_db};
+use rodio::Source;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tempo::Tempo;
+use timbre::Timbre;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Analysis {
+    /// Seconds of decoded audio.
+    pub duration: f64,
+    /// True peak in dBTP.
+    pub peak: f64,
+    /// RMS level over all channels in dBFS.
+    pub rms: f64,
+    /// Integrated loudness in LUFS.
+    pub lufs: f64,
+    /// The largest per-channel mean, as a fraction of full scale.
+    pub dc_offset: f64,
+    /// Declared by the file if it says, estimated otherwise.
+    pub bpm: Option<f64>,
+    /// Named in the file name if it is, estimated otherwise.
+    pub key: Option<Key>,
+    /// Timbre description for similarity search, see `timbre::GROUPS`.
+    pub features: Vec<f32>,
+}
+
+/// Accumulates the measurements one interleaved frame at a time.
+pub struct Analyzer {
+    sample_rate: u32,
+    frames: u64,
+    squares: f64,
+    sums: Vec<f64>,
+    true_peak: TruePeak,
+    loudness: Loudness,
+    tempo: Tempo,
+    chroma: Chroma,
+    timbre: Timbre,
+}
+
+impl Analyzer {
+    pub fn new(channels: usize, sample_rate: u32) -> Analyzer {
+        Analyzer {
+            sample_rate,
+            frames: 0,
+            squares: 0.0,
+            sums: vec![0.0; channels],
+            true_peak: TruePeak::new(channels),
+            loudness: Loudness::new(channels, sample_rate),
+            tempo: Tempo::new(sample_rate),
+            chroma: Chroma::new(sample_rate),
+            timbre: Timbre::new(sample_rate),
+        }
+    }
+
+    pub fn push(&mut self, frame: &[f32]) {
+        for (&x, sum) in frame.iter().zip(&mut self.sums) {
+            let x = x as f64;
+            self.squares += x * x;
+            *sum += x;
+        }
+        self.true_peak.push(frame);
+        self.loudness.push(frame);
+        let mono = frame.iter().sum::<f32>() / frame.len().max(1) as f32;
+        self.tempo.push(mono);
+        self.chroma.push(mono);
+        self.timbre.push(mono);
+        self.frames += 1;
+    }
+
+    pub fn finish(self) -> Analysis {
+        let frames = self.frames.max(1) as f64;
+        let samples = frames * self.sums.len().max(1) as f64;
+        Analysis {
+            duration: self.frames as f64 / self.sample_rate.max(1) as f64,
+            peak: to_db(self.true_peak.finish()),
+            rms: to_db((self.squares / samples).sqrt()),
+            lufs: self.loudness.finish(),
+            dc_offset: self
+                .sums
+                .iter()
+                .map(|sum| (sum / frames).abs())
+                .fold(0.0, f64::max),
+            bpm: self.tempo.finish(),
+            key: self.chroma.finish(),
+            features: self.timbre.finish(),
+        }
+    }
+}
+
+/// Decodes `path` from start to end; `None` if it cannot be decoded.
+pub fn analyze(path: &Path) -> Option<Analysis> {
+    let file = File::open(path).ok()?;
+    let decoder = rodio::Decoder::new(BufReader::new(file)).ok()?;
+    let channels = decoder.channels().max(1) as usize;
+    let mut analyzer = Analyzer::new(channels, decoder.sample_rate());
+    let mut frame = Vec::with_capacity(channels);
+    for sample in decoder.convert_samples::<f32>() {
+        frame.push(sample);
+        if frame.len() == channels {
+            analyzer.push(&frame);
+            frame.clear();
+        }
+    }
+    let mut analysis = analyzer.finish();
+    analysis.bpm = tempo::tagged(path).or(analysis.bpm);
+    analysis.key = key::from_name(path).or(analysis.key);
+    Some(analysis)
+}
@@ -0,0 +1,120 @@
+//! Reading the metadata chunks of wav files that hound skips over.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The body of the first `id` chunk in the RIFF/WAVE file at `path`.
+pub fn read_chunk(path: &Path, id: &[u8; 4]) -> Option<Vec<u8>> {
+    let mut file = BufReader::new(File::open(path).ok()?);
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut chunk_header = [0u8; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into().ok()?) as u64;
+        if &chunk_header[0..4] == id {
+            let mut body = Vec::new();
+            file.by_ref().take(size).read_to_end(&mut body).ok()?;
+            return Some(body);
+        }
+        // chunks are padded to an even length
+        file.seek(SeekFrom::Current((size + size % 2) as i64))
+            .ok()?;
+    }
+    None
+}
+
+/// The first loop of the `smpl` chunk, as the frames it starts and ends at
+/// (exclusive).
+pub fn loop_points(path: &Path) -> Option<(u64, u64)> {
+    let smpl = read_chunk(path, b"smpl")?;
+    let word = |at: usize| -> Option<u64> {
+        Some(u32::from_le_bytes(smpl.get(at..at + 4)?.try_into().ok()?) as u64)
+    };
+    if word(28)? == 0 {
+        return None;
+    }
+    // the loop records follow the 36 byte header
+    let (start, end) = (word(44)?, word(48)?);
+    (end >= start).then_some((start, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((body.len() as u32).to_le_bytes());
+        chunk.extend(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// A `smpl` body with `loops` loops, all from `start` to `end`.
+    fn smpl(loops: u32, start: u32, end: u32) -> Vec<u8> {
+        let mut body = vec![0; 28];
+        body.extend(loops.to_le_bytes());
+        body.extend([0; 4]);
+        for _ in 0..loops {
+            for word in [0, 0, start, end, 0, 0] {
+                body.extend(u32::to_le_bytes(word));
+            }
+        }
+        body
+    }
+
+    /// Writes a wav file made of `chunks` and returns its path.
+    fn wav(name: &str, chunks: &[Vec<u8>]) -> PathBuf {
+        let body: Vec<u8> = chunks.concat();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((body.len() as u32 + 4).to_le_bytes());
+        bytes.extend(b"WAVE");
+        bytes.extend(body);
+        let path =
+            std::env::temp_dir().join(format!("tundra-riff-{}-{}.wav", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_the_first_loop() {
+        let path = wav(
+            "loop",
+            &[
+                chunk(b"fmt ", &[0; 16]),
+                // odd sized, so the next chunk starts after a pad byte
+                chunk(b"junk", &[0; 3]),
+                chunk(b"smpl", &smpl(2, 100, 199)),
+                chunk(b"data", &[0; 8]),
+            ],
+        );
+        let points = loop_points(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(points, Some((100, 200)));
+    }
+
+    #[test]
+    fn ignores_missing_and_broken_loops() {
+        let none = wav("none", &[chunk(b"smpl", &smpl(0, 0, 0))]);
+        let backwards = wav("backwards", &[chunk(b"smpl", &smpl(1, 200, 100))]);
+        let short = wav("short", &[chunk(b"smpl", &smpl(1, 100, 200)[..40])]);
+        let missing = wav("missing", &[chunk(b"data", &[0; 8])]);
+        let points: Vec<_> = [&none, &backwards, &short, &missing]
+            .into_iter()
+            .map(|path| {
+                let points = loop_points(path);
+                fs::remove_file(path).unwrap();
+                points
+            })
+            .collect();
+        assert_eq!(points, [None, None, None, None]);
+    }
+}
@@ -0,0 +1,212 @@
+//! Tempo estimation from the autocorrelation of a spectral flux onset
+//! envelope, plus the tempo hints producers leave in names and ACID chunks.
+
+use super::fft::Fft;
+use super::riff;
+use std::path::Path;
+
+const FRAME: usize = 1024;
+const HOP: usize = 512;
+const MIN_BPM: f64 = 50.0;
+const MAX_BPM: f64 = 220.0;
+/// Shorter files are one-shots as far as tempo is concerned.
+const MIN_SECONDS: f64 = 2.0;
+
+pub struct Tempo {
+    sample_rate: u32,
+    samples: usize,
+    fft: Fft,
+    frame: Vec<f32>,
+    previous: Vec<f32>,
+    envelope: Vec<f64>,
+}
+
+impl Tempo {
+    pub fn new(sample_rate: u32) -> Tempo {
+        Tempo {
+            sample_rate,
+            samples: 0,
+            fft: Fft::new(FRAME),
+            frame: Vec::with_capacity(FRAME),
+            previous: vec![0.0; FRAME / 2 + 1],
+            envelope: Vec::new(),
+        }
+    }
+
+    /// Takes one sample of the mono downmix.
+    pub fn push(&mut self, x: f32) {
+        self.samples += 1;
+        self.frame.push(x);
+        if self.frame.len() < FRAME {
+            return;
+        }
+        let magnitudes = self.fft.magnitudes(&self.frame);
+        let mut flux = 0.0;
+        for (m, previous) in magnitudes.iter().zip(&mut self.previous) {
+            // log compression keeps loud sustained partials from dominating
+            let m = (1.0 + 100.0 * m).ln();
+            flux += (m - *previous).max(0.0) as f64;
+            *previous = m;
+        }
+        self.envelope.push(flux);
+        self.frame.drain(..HOP);
+    }
+
+    /// The most likely tempo in BPM, or `None` when the file is too short or
+    /// shows no periodicity.
+    pub fn finish(mut self) -> Option<f64> {
+        let rate = self.sample_rate as f64 / HOP as f64;
+        let duration = self.samples as f64 / self.sample_rate as f64;
+        if duration < MIN_SECONDS || self.envelope.is_empty() {
+            return None;
+        }
+        // the first frame has no predecessor, its flux is meaningless
+        self.envelope[0] = 0.0;
+        let mean = self.envelope.iter().sum::<f64>() / self.envelope.len() as f64;
+        let onsets: Vec<f64> = self.envelope.iter().map(|x| x - mean).collect();
+        let autocorrelation = |lag: usize| -> f64 {
+            if lag >= onsets.len() {
+                return 0.0;
+            }
+            let n = onsets.len() - lag;
+            (0..n).map(|t| onsets[t] * onsets[t + lag]).sum::<f64>() / n as f64
+        };
+        let energy = autocorrelation(0);
+        if energy <= 0.0 {
+            return None;
+        }
+        let min_lag = (60.0 * rate / MAX_BPM).floor().max(1.0) as usize;
+        let max_lag = ((60.0 * rate / MIN_BPM).ceil() as usize).min(onsets.len() / 2);
+        if min_lag + 2 > max_lag {
+            return None;
+        }
+        let r: Vec<f64> = (min_lag - 1..=max_lag + 1).map(autocorrelation).collect();
+        let r_at = |lag: usize| r[lag + 1 - min_lag];
+        let (best, score) = (min_lag..=max_lag)
+            .map(|lag| {
+                let bpm = 60.0 * rate / lag as f64;
+                // favour tempos around 120 to settle octave ambiguity
+                let prior = (-0.5 * (bpm / 120.0).log2().powi(2)).exp();
+                let harmonic = if 2 * lag <= max_lag {
+                    r_at(2 * lag)
+                } else {
+                    0.0
+                };
+                (lag, (r_at(lag) + 0.5 * harmonic) * prior)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        if score / energy < 0.05 {
+            return None;
+        }
+        let mut period = best as f64 + peak_offset(r_at(best - 1), r_at(best), r_at(best + 1));
+        // a frame is ~12ms, so measure the period over several beats where
+        // that quantisation matters less
+        for beats in [8, 4, 2] {
+            let center = (period * beats as f64).round() as usize;
+            if center + beats >= onsets.len() / 2 {
+                continue;
+            }
+            let peak = (center - beats..=center + beats)
+                .max_by(|&a, &b| autocorrelation(a).total_cmp(&autocorrelation(b)))?;
+            let offset = peak_offset(
+                autocorrelation(peak - 1),
+                autocorrelation(peak),
+                autocorrelation(peak + 1),
+            );
+            period = (peak as f64 + offset) / beats as f64;
+            break;
+        }
+        Some(snap_to_loop(60.0 * rate / period, duration))
+    }
+}
+
+/// Where the top of a parabola through three neighbouring values lies,
+/// relative to the middle one.
+fn peak_offset(a: f64, b: f64, c: f64) -> f64 {
+    let denominator = a - 2.0 * b + c;
+    if denominator < 0.0 {
+        (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    }
+}
+
+/// Loops hold a whole number of beats; if the estimate nearly fits, make it
+/// fit exactly.
+fn snap_to_loop(bpm: f64, duration: f64) -> f64 {
+    let beats = bpm * duration / 60.0;
+    let whole = beats.round();
+    if whole >= 4.0 && (beats - whole).abs() < 0.1 {
+        whole * 60.0 / duration
+    } else {
+        bpm
+    }
+}
+
+/// A tempo the file declares: the ACID chunk of a wav, or a `124bpm` style
+/// token in the file name.
+pub fn tagged(path: &Path) -> Option<f64> {
+    acid_tempo(path).or_else(|| from_name(path))
+}
+
+fn acid_tempo(path: &Path) -> Option<f64> {
+    let acid = riff::read_chunk(path, b"acid")?;
+    let tempo = f32::from_le_bytes(acid.get(20..24)?.try_into().ok()?) as f64;
+    plausible(tempo)
+}
+
+/// Finds `124bpm`, `124_bpm`, `124 BPM` or `bpm124` in the file name.
+pub fn from_name(path: &Path) -> Option<f64> {
+    let name = path.file_stem()?.to_string_lossy().to_lowercase();
+    let digits_before = |end: usize| {
+        let head = name[..end].trim_end_matches(['_', '-', ' ', '.']);
+        let rest = head.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        head[rest.len()..].trim_matches('.').parse::<f64>().ok()
+    };
+    let digits_after = |start: usize| {
+        let tail = name[start..].trim_start_matches(['_', '-', ' ']);
+        let rest = tail.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+        tail[..tail.len() - rest.len()]
+            .trim_matches('.')
+            .parse::<f64>()
+            .ok()
+    };
+    name.match_indices("bpm").find_map(|(i, _)| {
+        digits_before(i)
+            .and_then(plausible)
+            .or_else(|| digits_after(i + 3).and_then(plausible))
+    })
+}
+
+fn plausible(bpm: f64) -> Option<f64> {
+    (40.0..=300.0).contains(&bpm).then_some(bpm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bpm(name: &str) -> Option<f64> {
+        from_name(Path::new(name))
+    }
+
+    #[test]
+    fn reads_tempos_from_names() {
+        assert_eq!(bpm("loops/drums_124bpm.wav"), Some(124.0));
+        assert_eq!(bpm("Break 90 BPM.flac"), Some(90.0));
+        assert_eq!(bpm("house_128_bpm_Am.wav"), Some(128.0));
+        assert_eq!(bpm("BPM98 shaker.wav"), Some(98.0));
+        assert_eq!(bpm("swing_87.5bpm.wav"), Some(87.5));
+        // the number right before takes precedence over the one after
+        assert_eq!(bpm("take2_bpm_140.wav"), Some(140.0));
+    }
+
+    #[test]
+    fn ignores_implausible_and_untagged_numbers() {
+        assert_eq!(bpm("kick_808bpm.wav"), None);
+        assert_eq!(bpm("pad_Am_90.wav"), None);
+        assert_eq!(bpm("bpm.wav"), None);
+        // only the stem counts
+        assert_eq!(bpm("120bpm/snare.wav"), None);
+    }
+}
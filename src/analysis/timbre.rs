@@ -0,0 +1,189 @@
+//! A fixed length description of how a file sounds, for similarity search:
+//! MFCC statistics, spectral centroid, the shape of the amplitude envelope
+//! and its duration.
+
+use super::fft::Fft;
+use std::f32::consts::PI;
+
+const FRAME: usize = 2048;
+const HOP: usize = 1024;
+const MEL_BANDS: usize = 40;
+/// MFCCs kept after dropping c0, which only tracks overall level.
+const COEFFICIENTS: usize = 12;
+const ENVELOPE_POINTS: usize = 8;
+/// Frames quieter than this (about -80dBFS) are left out of the spectral
+/// statistics.
+const SILENCE_RMS: f32 = 1e-4;
+
+/// Lengths of the groups the feature vector is made of, in order: MFCC
+/// means, MFCC deviations, centroid mean and deviation, envelope, attack
+/// and duration. Similarity search weighs each group equally.
+pub const GROUPS: [usize; 5] = [COEFFICIENTS, COEFFICIENTS, 2, ENVELOPE_POINTS, 2];
+pub const FEATURES: usize = 2 * COEFFICIENTS + 2 + ENVELOPE_POINTS + 2;
+
+pub struct Timbre {
+    sample_rate: u32,
+    fft: Fft,
+    frame: Vec<f32>,
+    /// Sparse triangular filters: `(bin, weight)` pairs per mel band.
+    filters: Vec<Vec<(usize, f32)>>,
+    frames: usize,
+    sums: [f64; COEFFICIENTS],
+    squares: [f64; COEFFICIENTS],
+    centroid_sum: f64,
+    centroid_squares: f64,
+    /// RMS of every frame, silent or not.
+    envelope: Vec<f32>,
+    samples: usize,
+}
+
+pub fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+pub fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+impl Timbre {
+    pub fn new(sample_rate: u32) -> Timbre {
+        let bin_width = sample_rate as f32 / FRAME as f32;
+        let (low, high) = (
+            hz_to_mel(20.0),
+            hz_to_mel((sample_rate as f32 / 2.0).min(16000.0)),
+        );
+        let edges: Vec<f32> = (0..MEL_BANDS + 2)
+            .map(|i| mel_to_hz(low + (high - low) * i as f32 / (MEL_BANDS + 1) as f32))
+            .collect();
+        let filters = edges
+            .windows(3)
+            .map(|w| {
+                let (left, center, right) = (w[0], w[1], w[2]);
+                (0..=FRAME / 2)
+                    .filter_map(|bin| {
+                        let f = bin as f32 * bin_width;
+                        let weight = if f <= left || f >= right {
+                            0.0
+                        } else if f <= center {
+                            (f - left) / (center - left)
+                        } else {
+                            (right - f) / (right - center)
+                        };
+                        (weight > 0.0).then_some((bin, weight))
+                    })
+                    .collect()
+            })
+            .collect();
+        Timbre {
+            sample_rate,
+            fft: Fft::new(FRAME),
+            frame: Vec::with_capacity(FRAME),
+            filters,
+            frames: 0,
+            sums: [0.0; COEFFICIENTS],
+            squares: [0.0; COEFFICIENTS],
+            centroid_sum: 0.0,
+            centroid_squares: 0.0,
+            envelope: Vec::new(),
+            samples: 0,
+        }
+    }
+
+    /// Takes one sample of the mono downmix.
+    pub fn push(&mut self, x: f32) {
+        self.samples += 1;
+        self.frame.push(x);
+        if self.frame.len() == FRAME {
+            self.analyze_frame();
+            self.frame.drain(..HOP);
+        }
+    }
+
+    fn analyze_frame(&mut self) {
+        let rms = (self.frame.iter().map(|x| x * x).sum::<f32>() / FRAME as f32).sqrt();
+        self.envelope.push(rms);
+        if rms < SILENCE_RMS {
+            return;
+        }
+        let bin_width = self.sample_rate as f32 / FRAME as f32;
+        let magnitudes = self.fft.magnitudes(&self.frame);
+        let (weighted, total) = magnitudes
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(w, t), (bin, m)| {
+                (w + bin as f32 * bin_width * m, t + m)
+            });
+        if total > 0.0 {
+            let centroid = ((weighted / total).max(20.0) as f64).log2();
+            self.centroid_sum += centroid;
+            self.centroid_squares += centroid * centroid;
+        }
+        let bands: Vec<f32> = self
+            .filters
+            .iter()
+            .map(|filter| {
+                let energy: f32 = filter
+                    .iter()
+                    .map(|&(bin, w)| w * magnitudes[bin] * magnitudes[bin])
+                    .sum();
+                (energy + 1e-10).ln()
+            })
+            .collect();
+        for k in 0..COEFFICIENTS {
+            // DCT-II of the log mel energies, skipping c0
+            let c: f32 = bands
+                .iter()
+                .enumerate()
+                .map(|(n, e)| e * (PI * (k + 1) as f32 * (n as f32 + 0.5) / MEL_BANDS as f32).cos())
+                .sum();
+            self.sums[k] += c as f64;
+            self.squares[k] += (c * c) as f64;
+        }
+        self.frames += 1;
+    }
+
+    /// The feature vector, laid out as described by `GROUPS`.
+    pub fn finish(mut self) -> Vec<f32> {
+        // one-shots shorter than a frame still get a (zero padded) look
+        if self.envelope.is_empty() && !self.frame.is_empty() {
+            self.analyze_frame();
+        }
+        let n = self.frames.max(1) as f64;
+        let deviation = |sum: f64, squares: f64| (squares / n - (sum / n).powi(2)).max(0.0).sqrt();
+        let mut features = Vec::with_capacity(FEATURES);
+        features.extend(self.sums.iter().map(|s| (s / n) as f32));
+        features.extend(
+            self.sums
+                .iter()
+                .zip(&self.squares)
+                .map(|(&s, &q)| deviation(s, q) as f32),
+        );
+        features.push((self.centroid_sum / n) as f32);
+        features.push(deviation(self.centroid_sum, self.centroid_squares) as f32);
+        let peak = self.envelope.iter().copied().fold(0.0, f32::max);
+        let len = self.envelope.len();
+        for i in 0..ENVELOPE_POINTS {
+            let (start, end) = (i * len / ENVELOPE_POINTS, (i + 1) * len / ENVELOPE_POINTS);
+            // files with fewer frames than points repeat the nearest frame
+            let level = if start < end {
+                self.envelope[start..end].iter().sum::<f32>() / (end - start) as f32
+            } else {
+                self.envelope
+                    .get(start.min(len.saturating_sub(1)))
+                    .copied()
+                    .unwrap_or(0.0)
+            };
+            features.push(if peak > 0.0 { level / peak } else { 0.0 });
+        }
+        let peak_frame = self
+            .envelope
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map_or(0, |(i, _)| i);
+        let seconds_per_frame = HOP as f32 / self.sample_rate.max(1) as f32;
+        features.push((peak_frame as f32 * seconds_per_frame + 0.001).ln());
+        features.push((self.samples as f32 / self.sample_rate.max(1) as f32 + 0.01).ln());
+        features
+    }
+}
@@ -1,5 +1,6 @@
 #![feature(iter_array_chunks)]
 
+mod analysis;
 mod source;
 mod types;
 
@@ -23,13 +23,13 @@ impl<T, S> Callback<T, S> {
 
 impl<T, S> Iterator for Callback<T, S>
 where
-    T: Copy,
+    T: Clone,
 {
     type Item = S;
 
     #[inline]
     fn next(&mut self) -> Option<S> {
-        (self.callback)(self.args);
+        (self.callback)(self.args.clone());
         None
     }
 }
@@ -37,7 +37,7 @@ where
 impl<T, S> Source for Callback<T, S>
 where
     S: Sample,
-    T: Copy,
+    T: Clone,
 {
     #[inline]
     fn current_frame_len(&self) -> Option<usize> {
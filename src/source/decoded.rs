@@ -0,0 +1,324 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use rodio::Source;
+
+/// Samples decoded per lock of the shared buffer.
+const BLOCK: usize = 1 << 14;
+/// Loops crossfade over a second divided by this.
+const LOOP_FADE: u64 = 200;
+/// Loops with too little audio before them to crossfade into fade out, and
+/// back in, over a second divided by this.
+const LOOP_EDGE: u64 = 500;
+
+/// A file decoded into memory on its own thread. Readers can start before
+/// it has finished and wait for the part they need.
+pub struct Decoded {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Frames the header promises, if it says.
+    pub expected_frames: Option<usize>,
+    state: Mutex<State>,
+    grown: Condvar,
+}
+
+struct State {
+    /// Interleaved.
+    samples: Vec<i16>,
+    done: bool,
+}
+
+impl Decoded {
+    /// Starts decoding `source`. Decoding stops early once nothing else
+    /// holds on to the returned buffer.
+    pub fn spawn<I>(source: I) -> Arc<Decoded>
+    where
+        I: Source<Item = i16> + Send + 'static,
+    {
+        let channels = source.channels().max(1);
+        let sample_rate = source.sample_rate();
+        let expected_frames = source
+            .total_duration()
+            .map(|d| (d.as_secs_f64() * sample_rate as f64) as usize);
+        let decoded = Arc::new(Decoded {
+            channels,
+            sample_rate,
+            expected_frames,
+            state: Mutex::new(State {
+                // growing the buffer copies it while readers wait on the lock
+                samples: Vec::with_capacity(expected_frames.unwrap_or(0) * channels as usize),
+                done: false,
+            }),
+            grown: Condvar::new(),
+        });
+        let shared = Arc::clone(&decoded);
+        thread::spawn(move || {
+            let mut source = source;
+            let mut block = Vec::with_capacity(BLOCK);
+            loop {
+                block.extend(source.by_ref().take(BLOCK));
+                let done = block.len() < BLOCK || Arc::strong_count(&shared) == 1;
+                let mut state = shared.lock();
+                state.samples.extend_from_slice(&block);
+                state.done = done;
+                drop(state);
+                shared.grown.notify_all();
+                block.clear();
+                if done {
+                    break;
+                }
+            }
+        });
+        decoded
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Appends up to `max` samples, starting at sample `from`, to `buf`,
+    /// waiting for the decoder if it has not got that far yet. Returns
+    /// `false` past the end of the file.
+    pub fn read(&self, from: usize, max: usize, buf: &mut Vec<i16>) -> bool {
+        let mut state = self.lock();
+        while state.samples.len() <= from && !state.done {
+            state = self
+                .grown
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        let end = state.samples.len().min(from.saturating_add(max));
+        if from >= end {
+            return false;
+        }
+        buf.extend_from_slice(&state.samples[from..end]);
+        true
+    }
+
+    /// Frames decoded so far.
+    pub fn frames(&self) -> usize {
+        self.lock().samples.len() / self.channels as usize
+    }
+}
+
+/// Plays a `Decoded` buffer from any frame, optionally looping a region of
+/// it.
+pub struct DecodedSource {
+    pub audio: Arc<Decoded>,
+    /// The sample `block` ends at.
+    next: usize,
+    /// Samples to loop between, if looping.
+    region: Option<(usize, usize)>,
+    /// Samples before the end of the region that are crossfaded with those
+    /// before its start.
+    fade: usize,
+    /// Samples at either end of the region that fade out and back in, when
+    /// there is not enough before its start to crossfade with.
+    edge: usize,
+    /// Whether playback has gone back to the start of the region yet.
+    wrapped: bool,
+    block: Vec<i16>,
+    block_pos: usize,
+}
+
+impl DecodedSource {
+    #[inline]
+    pub fn new(audio: Arc<Decoded>, frame: u64) -> DecodedSource {
+        let next = frame as usize * audio.channels as usize;
+        DecodedSource {
+            audio,
+            next,
+            region: None,
+            fade: 0,
+            edge: 0,
+            wrapped: false,
+            block: Vec::with_capacity(BLOCK),
+            block_pos: 0,
+        }
+    }
+
+    /// Plays the frames from `start` to `end` over and over once it gets
+    /// there. Where there is enough audio before `start`, the end of each
+    /// pass fades into it so the jump back does not click; otherwise the end
+    /// fades out and the start of the next pass fades in.
+    #[inline]
+    pub fn looping(mut self, start: u64, end: u64) -> DecodedSource {
+        let channels = self.audio.channels as usize;
+        let end = end.max(start + 1);
+        let rate = self.audio.sample_rate as u64;
+        let fade = (rate / LOOP_FADE).min((end - start) / 2);
+        let (fade, edge) = if fade <= start {
+            (fade, 0)
+        } else {
+            (0, (rate / LOOP_EDGE).min((end - start) / 2))
+        };
+        self.region = Some((
+            start as usize * channels,
+            (end as usize).saturating_mul(channels),
+        ));
+        self.fade = fade as usize * channels;
+        self.edge = edge as usize * channels;
+        self
+    }
+
+    fn fill(&mut self) -> bool {
+        self.block.clear();
+        self.block_pos = 0;
+        let max = match self.region {
+            Some((start, end)) => {
+                if self.next >= end {
+                    self.next = start;
+                    self.wrapped = true;
+                }
+                end - self.next
+            }
+            None => BLOCK,
+        };
+        if !self.audio.read(self.next, max.min(BLOCK), &mut self.block) {
+            // a region running past the end of the file wraps there
+            match self.region {
+                Some((start, _)) if self.next > start => {
+                    self.next = start;
+                    self.wrapped = true;
+                    return self.fill();
+                }
+                _ => return false,
+            }
+        }
+        self.crossfade();
+        self.fade_edges();
+        self.next += self.block.len();
+        true
+    }
+
+    /// Mixes the samples of `block` that fall in the last `fade` of the
+    /// region with the ones as far before its start, fading from the former
+    /// to the latter.
+    fn crossfade(&mut self) {
+        let Some((start, end)) = self.region else {
+            return;
+        };
+        let fade_from = end - self.fade;
+        let block_end = self.next + self.block.len();
+        if self.fade == 0 || block_end <= fade_from {
+            return;
+        }
+        let from = self.next.max(fade_from);
+        let mut lead_in = Vec::with_capacity(block_end - from);
+        self.audio
+            .read(from - (end - start), block_end - from, &mut lead_in);
+        let channels = self.audio.channels as usize;
+        let frames = (self.fade / channels) as f32;
+        for (i, lead) in (from..block_end).zip(lead_in) {
+            // equal power, so the level holds through uncorrelated audio
+            let t = (((i - fade_from) / channels) as f32 + 0.5) / frames;
+            let t = t * std::f32::consts::FRAC_PI_2;
+            let sample = &mut self.block[i - self.next];
+            let mixed = *sample as f32 * t.cos() + lead as f32 * t.sin();
+            *sample = mixed.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+
+    /// Fades the samples of `block` in the last `edge` of the region out,
+    /// and once it has wrapped, the ones in the first `edge` back in.
+    fn fade_edges(&mut self) {
+        let Some((start, end)) = self.region else {
+            return;
+        };
+        if self.edge == 0 {
+            return;
+        }
+        let channels = self.audio.channels as usize;
+        let frames = (self.edge / channels) as f32;
+        for (i, sample) in (self.next..).zip(self.block.iter_mut()) {
+            let frame = if i + self.edge >= end {
+                (end - 1 - i) / channels
+            } else if self.wrapped && i < start + self.edge {
+                (i - start) / channels
+            } else {
+                continue;
+            };
+            *sample = (*sample as f32 * (frame as f32 + 0.5) / frames) as i16;
+        }
+    }
+}
+
+impl Iterator for DecodedSource {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        if self.block_pos == self.block.len() && !self.fill() {
+            return None;
+        }
+        self.block_pos += 1;
+        Some(self.block[self.block_pos - 1])
+    }
+}
+
+impl Source for DecodedSource {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.audio.channels
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// A mono second at 1 kHz that steps up by one each frame.
+    fn ramp() -> Arc<Decoded> {
+        let samples: Vec<i16> = (0..1000).collect();
+        Decoded::spawn(SamplesBuffer::new(1, 1000, samples))
+    }
+
+    #[test]
+    fn plays_from_a_frame() {
+        let played: Vec<i16> = DecodedSource::new(ramp(), 990).collect();
+        assert_eq!(played, (990..1000).collect::<Vec<i16>>());
+    }
+
+    #[test]
+    fn crossfades_into_the_audio_before_the_region() {
+        // five frames of crossfade at 1 kHz
+        let played: Vec<i16> = DecodedSource::new(ramp(), 100)
+            .looping(100, 200)
+            .take(200)
+            .collect();
+        assert_eq!(played[..95], (100..195).collect::<Vec<i16>>()[..]);
+        // the last frame is mostly lead-in, from just before the start
+        assert_eq!(played[99], 128);
+        assert_eq!(played[100..195], (100..195).collect::<Vec<i16>>()[..]);
+    }
+
+    #[test]
+    fn fades_out_and_in_at_the_start_of_the_file() {
+        // two frames of fade at 1 kHz
+        let played: Vec<i16> = DecodedSource::new(ramp(), 0)
+            .looping(0, 100)
+            .take(200)
+            .collect();
+        assert_eq!(played[..98], (0..98).collect::<Vec<i16>>()[..]);
+        assert_eq!(played[98..100], [73, 24]);
+        assert_eq!(played[100..102], [0, 0]);
+        assert_eq!(played[102..198], (2..98).collect::<Vec<i16>>()[..]);
+    }
+}
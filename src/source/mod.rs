@@ -1 +1,3 @@
 pub mod callback;
+pub mod decoded;
+pub mod position;
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use rodio::{Sample, Source};
+
+/// Passes a source through unchanged, reporting the playback position in
+/// frames every `interval` frames.
+pub struct Position<I> {
+    pub input: I,
+    pub callback: Box<dyn Send + Fn(u64)>,
+    pub interval: u64,
+    /// Where `input` starts, in frames from the start of the file.
+    pub start: u64,
+    /// Frames taken from `input` so far.
+    pub played: u64,
+    /// The frames `input` repeats once it reaches them, if it loops.
+    pub region: Option<(u64, u64)>,
+    channel: u16,
+}
+
+impl<I> Position<I> {
+    #[inline]
+    pub fn new(
+        input: I,
+        start: u64,
+        interval: u64,
+        callback: Box<dyn Send + Fn(u64)>,
+    ) -> Position<I> {
+        Position {
+            input,
+            callback,
+            interval: interval.max(1),
+            start,
+            played: 0,
+            region: None,
+            channel: 0,
+        }
+    }
+
+    /// Reports positions for an `input` that goes back to `start` whenever
+    /// it reaches `end`.
+    #[inline]
+    pub fn looping(mut self, start: u64, end: u64) -> Position<I> {
+        self.region = Some((start, end.max(start + 1)));
+        self
+    }
+}
+
+impl<I> Iterator for Position<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<I::Item> {
+        let sample = self.input.next()?;
+        self.channel += 1;
+        if self.channel >= self.input.channels() {
+            self.channel = 0;
+            self.played += 1;
+            if self.played.is_multiple_of(self.interval) {
+                let position = match self.region {
+                    Some((start, end)) if self.start + self.played >= end => {
+                        start + (self.start + self.played - end) % (end - start)
+                    }
+                    _ => self.start + self.played,
+                };
+                (self.callback)(position);
+            }
+        }
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<I> Source for Position<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
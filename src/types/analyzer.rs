@@ -0,0 +1,147 @@
+use super::AnalysisResult;
+pub use super::common::*;
+use crate::analysis;
+use futures::channel::mpsc::{Sender, UnboundedSender, unbounded};
+use futures::{FutureExt, SinkExt, StreamExt, stream};
+use iced::Subscription;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+/// Results are collected for this long before they are sent on, so the
+/// index is written in batches rather than once per file.
+const BATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct AnalysisProgress {
+    pub done: usize,
+    pub total: usize,
+    pub results: Vec<AnalysisResult>,
+}
+
+/// Hands files to the running analysis pass.
+#[derive(Debug, Clone)]
+pub struct Analyzer(UnboundedSender<Vec<(PathBuf, i64)>>);
+
+impl Analyzer {
+    /// Queues `(path, mtime)` pairs. Files already waiting or being measured
+    /// are not queued twice.
+    pub fn queue(&self, files: Vec<(PathBuf, i64)>) {
+        if !files.is_empty() {
+            let _ = self.0.unbounded_send(files);
+        }
+    }
+}
+
+enum Event {
+    Queued(Vec<(PathBuf, i64)>),
+    Analyzed(AnalysisResult),
+}
+
+/// Starts the analysis workers and reports `Message::AnalyzerReady` once
+/// files can be queued. Batches of results arrive as
+/// `Message::AnalysisProgress`; a pass ends, with `done == total`, when the
+/// queue runs dry.
+pub fn analyze() -> Subscription<Message> {
+    Subscription::run_with_id("analysis", iced::stream::channel(16, run))
+}
+
+async fn run(mut output: Sender<Message>) {
+    let (queue_tx, queue_rx) = unbounded();
+    if output
+        .send(Message::AnalyzerReady(Analyzer(queue_tx)))
+        .await
+        .is_err()
+    {
+        return;
+    }
+    let (jobs_tx, jobs_rx) = mpsc::channel::<(PathBuf, i64)>();
+    let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+    let (results_tx, results_rx) = unbounded();
+    // leave a core for the ui and playback
+    let workers = thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1));
+    for _ in 0..workers {
+        let jobs_rx = jobs_rx.clone();
+        let results_tx = results_tx.clone();
+        thread::spawn(move || {
+            loop {
+                let next = match jobs_rx.lock() {
+                    Ok(jobs_rx) => jobs_rx.recv(),
+                    Err(_) => break,
+                };
+                // the subscription was dropped
+                let Ok((path, mtime)) = next else {
+                    break;
+                };
+                let analysis = analysis::analyze(&path);
+                let result = AnalysisResult {
+                    path,
+                    mtime,
+                    analysis,
+                };
+                if results_tx.unbounded_send(result).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(results_tx);
+    let mut events = stream::select(queue_rx.map(Event::Queued), results_rx.map(Event::Analyzed));
+    // keyed on the mtime too, so a file that changes while it is measured
+    // is measured again
+    let mut pending: HashSet<(PathBuf, i64)> = HashSet::new();
+    let (mut done, mut total) = (0, 0);
+    while let Some(event) = events.next().await {
+        let mut batch = vec![event];
+        if matches!(batch[0], Event::Analyzed(_)) {
+            async_std::task::sleep(BATCH_INTERVAL).await;
+        }
+        while let Some(Some(event)) = events.next().now_or_never() {
+            batch.push(event);
+        }
+        let mut results = Vec::new();
+        let mut queued = false;
+        for event in batch {
+            match event {
+                Event::Queued(files) => {
+                    for (path, mtime) in files {
+                        if !pending.insert((path.clone(), mtime)) {
+                            continue;
+                        }
+                        // a new pass counts from zero
+                        if pending.len() == 1 {
+                            (done, total) = (0, 0);
+                        }
+                        total += 1;
+                        queued = true;
+                        if jobs_tx.send((path, mtime)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Event::Analyzed(result) => {
+                    pending.remove(&(result.path.clone(), result.mtime));
+                    done += 1;
+                    results.push(result);
+                }
+            }
+        }
+        if !queued && results.is_empty() {
+            continue;
+        }
+        let progress = AnalysisProgress {
+            done,
+            total,
+            results,
+        };
+        if output
+            .send(Message::AnalysisProgress(progress))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
@@ -1,13 +1,15 @@
+use super::search::{SearchDSL, SearchScope};
 use super::*;
+use crate::analysis::loudness;
+use futures::channel::mpsc::UnboundedReceiver;
 use futures::future::{AbortHandle, Abortable};
 use futures::*;
-use fuzzy_matcher::skim::SkimMatcherV2;
-use fuzzy_matcher::FuzzyMatcher;
 
-use iced::{Element, Length, Task};
+use iced::keyboard;
+use iced::{Element, Length, Subscription, Task};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::{collections::hash_map::HashMap, path::PathBuf};
-use walkdir::WalkDir;
 
 pub struct App {
     pub file_selector: FileSelector,
@@ -16,75 +18,54 @@ pub struct App {
     pub player: Player,
     pub search_thread: AbortHandle,
     pub dir_cache: DirCache,
+    pub indexing: HashSet<PathBuf>,
+    pub status: Option<String>,
+    /// Errors shown until dismissed, oldest first.
+    pub notifications: Vec<Error>,
+    pub config: Config,
+    pub keymap: Keymap,
+    pub show_shortcuts: bool,
+    /// The settings panel, while it is open.
+    pub settings: Option<Settings>,
+    /// Set when `TUNDRA_OUTPUT` overrides the configured output.
+    pub output_from_env: bool,
+    pub search_scope: SearchScope,
+    /// Takes files that need measuring, once the analysis pass has started.
+    pub analyzer: Option<analyzer::Analyzer>,
+    /// `(done, total)` while the analysis pass is running.
+    pub analysis_progress: Option<(usize, usize)>,
 }
 
-pub struct DirCache(HashMap<PathBuf, Vec<PathBuf>>);
-
-impl DirCache {
-    fn new() -> DirCache {
-        DirCache(HashMap::new())
-    }
-
-    fn insert(&mut self, k: PathBuf, v: Vec<PathBuf>) -> Option<Vec<PathBuf>> {
-        self.0.insert(k, v)
-    }
-
-    fn get(&self, k: &PathBuf ) -> Option<&Vec<PathBuf>>  {
-        self.0.get(k)
-    }
-
-    fn contains_key(&self, k: &PathBuf ) -> bool {
-        self.0.contains_key(k)
-    }
-
-    fn get_path() -> std::option::Option<std::path::PathBuf> {
-        match dirs::cache_dir() {
-            Some(mut cache_dir) => {
-                cache_dir.push("tundra");
-                let _ = std::fs::create_dir(cache_dir.clone());
-                cache_dir.push("dir_cache");
-                cache_dir.set_extension("bin");
-                Some(cache_dir)
-            }
-            None => None,
-        }
-    }
-
-    fn get_dir_cache() -> DirCache {
-        match DirCache::get_path() {
-            Some(dir_cache) => match std::fs::read(dir_cache) {
-                Ok(s) => bincode::deserialize(&s).map_or(DirCache::new(), DirCache),
-                Err(_) => DirCache::new(),
-            },
-            None => {
-                DirCache::new()
-            },
-        }
-    }
-
-    fn persist(&self) {
-        match DirCache::get_path() {
-            Some(dir_cache) => {
-                std::fs::write(dir_cache, bincode::serialize(&self.0).unwrap())
-                    .unwrap();
-            }
-            None => (),
-        };
-    }
-}
+/// Older notifications are dropped beyond this.
+const MAX_NOTIFICATIONS: usize = 5;
+/// Seconds the seek keys move by.
+const SEEK_STEP: f64 = 1.0;
+/// Rows the page keys move the selection by.
+const PAGE_ROWS: isize = 10;
 
 pub fn app() {
     iced::application(App::title, App::update, App::view)
-        //.subscription(App::subscription)
+        .subscription(App::subscription)
         //.theme(App::theme)
         .antialiasing(true)
-        .run()
+        .run_with(App::new)
         .unwrap()
 }
 
+/// Turns the player thread's messages into `Message::PlayerMsg`s, one at a
+/// time.
+fn listen(receiver: UnboundedReceiver<PlayerMsg>) -> Task<Message> {
+    Task::perform(receiver.into_future(), |x| {
+        Message::PlayerMsg((x.0, Arc::new(x.1)))
+    })
+}
+
 impl Default for App {
     fn default() -> App {
-        let current_dir = std::env::current_dir().unwrap();
+        let current_dir = std::env::current_dir()
+            .ok()
+            .or_else(dirs::home_dir)
+            .unwrap_or_else(|| PathBuf::from("/"));
         let file_selector = FileSelector::new(&current_dir);
         let menu = MainMenu::new();
         let player = Player::new();
@@ -97,11 +78,53 @@ impl Default for App {
             player,
             search_thread,
             dir_cache,
+            indexing: HashSet::new(),
+            status: None,
+            notifications: Vec::new(),
+            config: Config::load(),
+            keymap: Keymap::default(),
+            show_shortcuts: false,
+            settings: None,
+            output_from_env: false,
+            search_scope: SearchScope::CurrentDir,
+            analyzer: None,
+            analysis_progress: None,
         }
     }
 }
 
 impl App {
+    /// Starts indexing every configured library that is not indexed yet.
+    pub fn new() -> (App, Task<Message>) {
+        let mut app = App::default();
+        app.player.controls.volume = app.config.volume;
+        app.player.controls.loudness_match = app.config.loudness_match;
+        app.player.controls.lanes = app.config.lanes;
+        app.player.controls.show_spectrogram = app.config.show_spectrogram;
+        app.player.controls.spectrogram = app.config.spectrogram;
+        app.keymap = Keymap::new(&app.config.keybindings);
+        app.player.output = match Output::from_env() {
+            Some(output) => {
+                app.output_from_env = true;
+                output
+            }
+            None => app.config.output.clone(),
+        };
+        let current_dir = app.file_selector.current_dir.clone();
+        if let Err(e) = app.file_selector.change_dir(&current_dir) {
+            app.notify(e);
+        }
+        app.file_selector.attach_records(&app.dir_cache);
+        let libraries: Vec<PathBuf> = app
+            .config
+            .libraries
+            .iter()
+            .map(|l| l.path.clone())
+            .collect();
+        let tasks: Vec<Task<Message>> = libraries.into_iter().map(|p| app.index_dir(p)).collect();
+        (app, Task::batch(tasks))
+    }
+
     pub fn title(&self) -> String {
         String::from("Tundra Sample Browser")
     }
@@ -112,16 +135,26 @@ impl App {
                 match &selected_file {
                     Some(file_path) => {
                         if file_path.is_dir() {
-                            self.file_selector = FileSelector::new(file_path);
+                            if let Err(e) = self.file_selector.change_dir(file_path) {
+                                self.notify(e);
+                            }
+                            self.file_selector.attach_records(&self.dir_cache);
                         } else {
-                            let receiver = self.player.play_file(file_path.to_owned());
+                            let gain = self.loudness_gain(file_path);
+                            let receiver = match self.player.play_file(file_path.to_owned(), gain) {
+                                Ok(receiver) => receiver,
+                                Err(e) => {
+                                    self.notify(e);
+                                    return Task::none();
+                                }
+                            };
+                            self.file_selector.broken.remove(file_path);
+                            let waveform = self.player.load_waveform(file_path.to_owned());
                             self.file_selector.selected_file =
                                 self.file_selector.file_list.iter().position(|x| {
                                     selected_file.as_ref().map_or(false, |y| y == &x.file_path)
                                 });
-                            return Task::perform(receiver.into_future(), |x| {
-                                Message::PlayerMsg((x.0, Arc::new(x.1)))
-                            });
+                            return Task::batch([listen(receiver), waveform]);
                         }
                     }
                     None => {
@@ -132,125 +165,239 @@ impl App {
                 Task::none()
             }
 
+            Message::MoveSelection(by) => {
+                let path = match self.file_selector.move_selection(by) {
+                    Some(button) => button.file_path.clone(),
+                    None => return Task::none(),
+                };
+                let scroll = self.file_selector.scroll_to_selected();
+                if path.is_dir() {
+                    return scroll;
+                }
+                let play = self.update(Message::SelectedFile(Some(path)));
+                Task::batch([scroll, play])
+            }
+
+            Message::OpenSelected => match self.file_selector.selected() {
+                Some(button) => {
+                    let path = button.file_path.clone();
+                    self.update(Message::SelectedFile(Some(path)))
+                }
+                None => Task::none(),
+            },
+
+            Message::GoUp => {
+                let from = self.file_selector.current_dir.clone();
+                let parent = match from.parent() {
+                    Some(parent) => parent.to_path_buf(),
+                    None => return Task::none(),
+                };
+                let index = self.update(Message::ChangeDirectory(parent));
+                self.file_selector.select(&from);
+                Task::batch([index, self.file_selector.scroll_to_selected()])
+            }
+
+            Message::FocusSearch => FileSelector::focus_search(),
+
+            Message::KeyPressed(key, modifiers) => match self.keymap.action(&key, modifiers) {
+                Some(action) => self.perform(action),
+                None => Task::none(),
+            },
+
+            Message::SeekBy(seconds) => {
+                self.player.seek_by(seconds);
+                Task::none()
+            }
+
+            Message::Zoom(steps) => {
+                if let Some(waveform) = &mut self.player.waveform {
+                    waveform.zoom_at(steps, 0.5);
+                }
+                Task::none()
+            }
+
+            Message::ZoomAt(steps, at) => {
+                if let Some(waveform) = &mut self.player.waveform {
+                    waveform.zoom_at(steps, at);
+                }
+                Task::none()
+            }
+
+            Message::Pan(by) => {
+                if let Some(waveform) = &mut self.player.waveform {
+                    waveform.pan(by);
+                }
+                Task::none()
+            }
+
+            Message::ScrollTo(at) => {
+                if let Some(waveform) = &mut self.player.waveform {
+                    waveform.scroll_to(at);
+                }
+                Task::none()
+            }
+
+            Message::ZoomToSelection => {
+                if let Some(waveform) = &mut self.player.waveform {
+                    waveform.zoom_to_selection();
+                }
+                Task::none()
+            }
+
+            Message::ToggleFavorite => {
+                let path = match self.file_selector.selected() {
+                    Some(button) => button.file_path.clone(),
+                    None => return Task::none(),
+                };
+                if !self.config.favorites.remove(&path) {
+                    self.config.favorites.insert(path);
+                }
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                Task::none()
+            }
+
+            Message::Reveal => {
+                let path = match self.file_selector.selected() {
+                    Some(button) => button.file_path.clone(),
+                    None => self.file_selector.current_dir.clone(),
+                };
+                if let Err(e) = reveal(&path) {
+                    self.notify(e);
+                }
+                Task::none()
+            }
+
+            Message::ToggleShortcuts => {
+                self.show_shortcuts = !self.show_shortcuts;
+                Task::none()
+            }
+
+            Message::FocusFileList => {
+                let unfocus = FileSelector::unfocus_search();
+                if self.file_selector.selected_file.is_some() {
+                    return unfocus;
+                }
+                Task::batch([unfocus, self.update(Message::MoveSelection(1))])
+            }
+
             Message::ChangeDirectory(parent_dir) => {
-                self.file_selector = FileSelector::new(&parent_dir);
-                if !self.dir_cache.contains_key(&self.file_selector.current_dir) {
-                    let walker = future::lazy(|_| {
-                        let children: Vec<PathBuf> = WalkDir::new(&parent_dir)
-                            .max_depth(100)
-                            .max_open(100)
-                            .follow_links(true)
-                            .into_iter()
-                            .filter_entry(|e| FileList::file_filter(e.path().into()))
-                            .filter_map(|e| match e {
-                                Ok(e) => Some(e.path().to_path_buf()),
-                                Err(_) => None,
-                            })
-                            .collect();
-                        (parent_dir, children)
-                    });
-                    Task::perform(walker, Message::InsertDircache)
-                } else {
-                    Task::none()
+                if let Err(e) = self.file_selector.change_dir(&parent_dir) {
+                    self.notify(e);
+                    return Task::none();
                 }
+                self.file_selector.attach_records(&self.dir_cache);
+                self.index_dir(parent_dir)
             }
 
             Message::Search(search_str) => {
                 self.search_thread.abort();
-                match self.dir_cache.get(&self.file_selector.current_dir) {
-                    // dir is cached
-                    Some(children) => {
-                        let (abort_handle, abort_reg) = AbortHandle::new_pair();
-                        self.search_thread = abort_handle;
-                        self.file_selector.search_value = search_str.clone();
-                        if search_str.len() > 2 {
-                            let matcher = SkimMatcherV2::default();
-                            let children_clone = children.clone();
-                            let file_list = Abortable::new(
-                                async move {
-                                    async_std::task::sleep(std::time::Duration::from_millis(200))
-                                        .await;
-                                    children_clone
-                                        .iter()
-                                        .filter_map(|e| {
-                                            if matcher
-                                                .fuzzy_match(
-                                                    e.to_string_lossy().as_ref(),
-                                                    &search_str,
-                                                )
-                                                .is_some()
-                                            {
-                                                Some(e.to_owned())
-                                            } else {
-                                                None
-                                            }
-                                        })
-                                        .collect()
-                                },
-                                abort_reg,
-                            );
-                            Task::perform(file_list, Message::SearchCompleted)
-                        } else {
-                            self.file_selector.file_list =
-                                FileList::new(&self.file_selector.current_dir);
-                            Task::none()
-                        }
+                self.file_selector.search_value = search_str.clone();
+                if search_str.len() <= 2 {
+                    self.file_selector.search_error = None;
+                    if let Err(e) = self.file_selector.refresh() {
+                        self.notify(e);
                     }
-                    // dir not cached
-                    None => {
-                        let (abort_handle, abort_reg) = AbortHandle::new_pair();
-                        self.search_thread = abort_handle;
-                        self.file_selector.search_value = search_str.clone();
-                        let current_dir = self.file_selector.current_dir.clone();
-                        if search_str.len() > 2 {
-                            let matcher = SkimMatcherV2::default();
-                            let file_list = Abortable::new(
-                                async move {
-                                    async_std::task::sleep(std::time::Duration::from_millis(300))
-                                        .await;
-                                    WalkDir::new(&current_dir)
-                                        .max_depth(100)
-                                        .max_open(100)
-                                        .follow_links(true)
-                                        .into_iter()
-                                        .filter_entry(|e| FileList::file_filter(e.path().into()))
-                                        .filter_map(|e| match e {
-                                            Ok(e) => {
-                                                let epath = e.path();
-                                                if matcher
-                                                    .fuzzy_match(
-                                                        epath.to_string_lossy().as_ref(),
-                                                        &search_str,
-                                                    )
-                                                    .is_some()
-                                                {
-                                                    Some(epath.to_path_buf())
-                                                } else {
-                                                    None
-                                                }
-                                            }
-                                            Err(_) => None,
-                                        })
-                                        .collect()
-                                },
-                                abort_reg,
-                            );
-                            Task::perform(file_list, Message::SearchCompleted)
-                        } else {
-                            self.file_selector.file_list =
-                                FileList::new(&self.file_selector.current_dir);
-                            Task::none()
-                        }
+                    self.file_selector.attach_records(&self.dir_cache);
+                    return Task::none();
+                }
+                let query = match SearchDSL::parse(&search_str) {
+                    Ok(query) => query,
+                    Err(e) => {
+                        self.file_selector.search_error = Some(e.to_string());
+                        return Task::none();
                     }
+                };
+                self.file_selector.search_error = None;
+                let roots = self
+                    .search_scope
+                    .roots(&self.file_selector.current_dir, &self.config.libraries);
+                let unindexed: Vec<PathBuf> = roots
+                    .iter()
+                    .filter(|(_, root)| !self.dir_cache.contains_key(root))
+                    .map(|(_, root)| root.clone())
+                    .collect();
+                if !unindexed.is_empty() {
+                    // searched again once the walks land in InsertDircache
+                    let walks: Vec<Task<Message>> = unindexed
+                        .into_iter()
+                        .map(|root| self.index_dir(root))
+                        .collect();
+                    return Task::batch(walks);
                 }
+                let (abort_handle, abort_reg) = AbortHandle::new_pair();
+                self.search_thread = abort_handle;
+                let file_list = Abortable::new(
+                    async move {
+                        async_std::task::sleep(std::time::Duration::from_millis(200)).await;
+                        let groups = DirCache::open().and_then(|c| {
+                            roots
+                                .into_iter()
+                                .map(|(base, root)| c.entries_under(&root).map(|r| (base, r)))
+                                .collect::<rusqlite::Result<Vec<_>>>()
+                        });
+                        match groups {
+                            Ok(groups) => query.search(
+                                groups
+                                    .iter()
+                                    .map(|(base, r)| (base.as_path(), r.as_slice())),
+                            ),
+                            Err(e) => {
+                                eprintln!("search: {}", e);
+                                Vec::new()
+                            }
+                        }
+                    },
+                    abort_reg,
+                );
+                Task::perform(file_list, Message::SearchCompleted)
+            }
+
+            Message::SearchScopeSelected(scope) => {
+                self.search_scope = scope;
+                let search_value = self.file_selector.search_value.clone();
+                self.update(Message::Search(search_value))
             }
 
             Message::SearchCompleted(file_list_res) => {
                 if let Ok(file_list) = file_list_res {
-                    self.file_selector.file_list = file_list
-                        .iter()
-                        .map(|x| FileButton::new(x.to_path_buf(), &self.file_selector.current_dir))
-                        .collect();
+                    self.file_selector.replace_files(
+                        file_list
+                            .into_iter()
+                            .map(|hit| {
+                                FileButton::new(hit.path, &hit.base)
+                                    .with_highlights(&hit.m.indices)
+                                    .with_record(hit.record)
+                            })
+                            .collect(),
+                    );
+                    self.file_selector.attach_records(&self.dir_cache);
+                }
+                Task::none()
+            }
+
+            Message::AddLibrary => {
+                let dir = self.file_selector.current_dir.clone();
+                if self.config.libraries.iter().any(|l| l.path == dir) {
+                    return Task::none();
+                }
+                self.config.libraries.push(Library::new(dir.clone()));
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                self.index_dir(dir)
+            }
+
+            Message::RemoveLibrary(i) => {
+                if i < self.config.libraries.len() {
+                    let library = self.config.libraries.remove(i);
+                    if let Err(e) = self.config.persist() {
+                        self.notify(e);
+                    }
+                    if self.search_scope == SearchScope::Library(library) {
+                        self.search_scope = SearchScope::CurrentDir;
+                    }
                 }
                 Task::none()
             }
@@ -269,27 +416,258 @@ impl App {
                 Task::none()
             }
 
+            Message::VolumeChanged(volume) => {
+                self.player.set_volume(volume);
+                Task::none()
+            }
+
+            Message::VolumeCommit => {
+                self.config.volume = self.player.controls.volume;
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                Task::none()
+            }
+
+            Message::ToggleLoop(looping) => {
+                self.player.set_looping(looping);
+                Task::none()
+            }
+
+            Message::LanesSelected(lanes) => {
+                self.player.controls.lanes = lanes;
+                if let Some(waveform) = &mut self.player.waveform {
+                    waveform.set_lanes(lanes);
+                }
+                self.config.lanes = lanes;
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                Task::none()
+            }
+            Message::ToggleLoudnessMatch(enabled) => {
+                self.player.controls.loudness_match = enabled;
+                self.player.apply_volume();
+                self.config.loudness_match = enabled;
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                Task::none()
+            }
+
             Message::StopPlayback => {
                 self.player.stop();
                 Task::none()
             }
 
             Message::InsertDircache((parent_dir, children)) => {
-                self.dir_cache.insert(parent_dir, children);
-                self.dir_cache.persist();
+                self.indexing.remove(&parent_dir);
+                if let Err(e) = self.dir_cache.insert(parent_dir.clone(), children) {
+                    self.notify(Error::Index(format!(
+                        "could not index {}: {}",
+                        parent_dir.display(),
+                        e
+                    )));
+                    return Task::none();
+                }
+                self.analyze_pending(&[parent_dir]);
+                if !self.file_selector.search_value.is_empty() {
+                    let search_value = self.file_selector.search_value.clone();
+                    return self.update(Message::Search(search_value));
+                }
                 Task::none()
             }
 
+            Message::IndexChanged(update) => {
+                if let Err(e) = self.dir_cache.apply(&update) {
+                    self.notify(Error::Index(format!("could not update the index: {}", e)));
+                    return Task::none();
+                }
+                self.analyze_upserted(&update);
+                self.refresh_file_list(update.paths())
+            }
+
             Message::InvalidateDircache() => {
-                self.dir_cache = DirCache::new();
-                self.dir_cache.persist();
+                if let Err(e) = self.dir_cache.clear() {
+                    self.notify(Error::Index(format!("could not clear the index: {}", e)));
+                }
+                if let Err(e) = Peaks::clear_cache() {
+                    self.notify(Error::Index(format!(
+                        "could not clear the peak cache: {}",
+                        e
+                    )));
+                }
+                Task::none()
+            }
+
+            Message::RescanLibrary => {
+                self.status = Some(String::from("Rescanning library..."));
+                let rescan = future::lazy(|_| {
+                    DirCache::open()
+                        .and_then(|dir_cache| Rescan::run(&dir_cache))
+                        .map(|rescan| (rescan.update, rescan.report))
+                        .map_err(|e| e.to_string())
+                });
+                Task::perform(rescan, Message::RescanCompleted)
+            }
+
+            Message::ToggleSettings => {
+                if self.settings.take().is_some() {
+                    return Task::none();
+                }
+                self.settings = Some(Settings {
+                    devices: Vec::new(),
+                });
+                // some hosts take a while to enumerate their devices
+                Task::perform(
+                    future::lazy(|_| output::devices()),
+                    Message::OutputDevicesListed,
+                )
+            }
+
+            Message::ToggleSpectrogram(show) => {
+                self.player.set_spectrogram(show, self.config.spectrogram);
+                self.config.show_spectrogram = show;
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                Task::none()
+            }
+
+            Message::SpectrogramChanged(spectrogram) => {
+                self.player
+                    .set_spectrogram(self.config.show_spectrogram, spectrogram);
+                self.config.spectrogram = spectrogram;
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                Task::none()
+            }
+
+            Message::OutputDevicesListed(devices) => {
+                if let Some(settings) = &mut self.settings {
+                    settings.devices = devices;
+                }
+                Task::none()
+            }
+
+            Message::OutputSelected(output) => {
+                self.config.output = output.clone();
+                if let Err(e) = self.config.persist() {
+                    self.notify(e);
+                }
+                match self.player.set_output(output) {
+                    Some(receiver) => listen(receiver),
+                    None => Task::none(),
+                }
+            }
+
+            Message::RescanCompleted(Ok((update, report))) => {
+                self.status = Some(report.to_string());
+                if let Err(e) = self.dir_cache.apply(&update) {
+                    self.status = Some(format!("Rescan failed: {}", e));
+                    return Task::none();
+                }
+                self.analyze_upserted(&update);
+                self.refresh_file_list(update.paths())
+            }
+
+            Message::RescanCompleted(Err(e)) => {
+                self.status = Some(format!("Rescan failed: {}", e));
+                Task::none()
+            }
+
+            Message::AnalyzerReady(analyzer) => {
+                self.analyzer = Some(analyzer);
+                let roots = self.dir_cache.roots().to_vec();
+                self.analyze_pending(&roots);
+                Task::none()
+            }
+
+            Message::AnalysisProgress(progress) => {
+                self.analysis_progress =
+                    Some((progress.done, progress.total)).filter(|(done, total)| done < total);
+                if let Err(e) = self.dir_cache.store_analysis(&progress.results) {
+                    self.notify(Error::Index(format!(
+                        "could not store analysis results: {}",
+                        e
+                    )));
+                    return Task::none();
+                }
+                let changed: HashSet<&Path> =
+                    progress.results.iter().map(|r| r.path.as_path()).collect();
+                self.file_selector.update_records(&self.dir_cache, &changed);
+                if let Some(file) = self.player.file.clone()
+                    && changed.contains(file.as_path())
+                {
+                    let gain = self.loudness_gain(&file);
+                    self.player.set_gain(gain);
+                }
+                Task::none()
+            }
+
+            Message::WaveFormChunk(chunk) => {
+                self.player.add_waveform_chunk(chunk);
+                Task::none()
+            }
+
+            Message::FindSimilar => {
+                let target = match self
+                    .file_selector
+                    .selected_file
+                    .and_then(|i| self.file_selector.file_list.get(i))
+                {
+                    Some(button) => button.file_path.clone(),
+                    None => return Task::none(),
+                };
+                // a pending search would replace the results
+                self.search_thread.abort();
+                let roots = self
+                    .search_scope
+                    .roots(&self.file_selector.current_dir, &self.config.libraries);
+                self.status = Some(String::from("Looking for similar sounds..."));
+                let similar = future::lazy(move |_| {
+                    similar::find_similar(&target, &roots).map(|hits| (target, hits))
+                });
+                Task::perform(similar, Message::SimilarFound)
+            }
+
+            Message::SimilarFound(Ok((target, hits))) => {
+                self.status = Some(format!(
+                    "{} sounds similar to {}",
+                    hits.len(),
+                    target.file_name().unwrap_or_default().to_string_lossy()
+                ));
+                self.file_selector.search_value.clear();
+                self.file_selector.selected_file = None;
+                self.file_selector.file_list = hits
+                    .into_iter()
+                    .map(|hit| FileButton::new(hit.path, &hit.base).with_record(hit.record))
+                    .collect();
+                self.file_selector.attach_records(&self.dir_cache);
+                Task::none()
+            }
+
+            Message::SimilarFound(Err(e)) => {
+                self.status = Some(format!("Find similar failed: {}", e));
+                Task::none()
+            }
+
+            Message::SortBy(column) => {
+                self.file_selector.sort_by(column);
                 Task::none()
             }
 
             Message::PlayerMsg((msg, recv)) => {
                 match msg {
                     Some(PlayerMsg::PlayingStored) => (),
-                    Some(PlayerMsg::SinkEmpty) => self.player.pause(),
+                    Some(PlayerMsg::SinkEmpty) => {
+                        self.player.pause();
+                        // playing again starts over
+                        self.player.set_position(0);
+                    }
+                    Some(PlayerMsg::Position(frames)) => self.player.set_position(frames),
+                    Some(PlayerMsg::Error(e)) => self.notify(e),
                     None => return Task::none(),
                 }
                 match Arc::into_inner(recv) {
@@ -297,9 +675,7 @@ impl App {
                         eprintln!("Message::PlayerMsg Arc::into_inner failed");
                         Task::none()
                     }
-                    Some(recv) => Task::perform(recv.into_future(), |x| {
-                        Message::PlayerMsg((x.0, Arc::new(x.1)))
-                    }),
+                    Some(recv) => listen(recv),
                 }
             }
             Message::Seek(p) => {
@@ -313,6 +689,20 @@ impl App {
                 }
                 Task::none()
             }
+            Message::SeekTo(frames) => {
+                self.player.seek_to(frames);
+                Task::none()
+            }
+            Message::LoopRegion(start, end) => {
+                self.player.loop_region(start, end);
+                Task::none()
+            }
+            Message::DismissNotification(i) => {
+                if i < self.notifications.len() {
+                    self.notifications.remove(i);
+                }
+                Task::none()
+            }
             Message::VResizeFileSelector(position) => {
                 self.file_selector_divider_vpos = Some(position);
                 Task::none()
@@ -320,23 +710,207 @@ impl App {
         }
     }
 
+    pub fn subscription(&self) -> Subscription<Message> {
+        Subscription::batch([
+            watcher::watch(self.dir_cache.roots()),
+            analyzer::analyze(),
+            // keys that reach us are the ones no widget used, so bindings
+            // never fire while typing in the search box
+            keyboard::on_key_press(|key, modifiers| Some(Message::KeyPressed(key, modifiers))),
+        ])
+    }
+
+    /// Queues the files below `roots` whose measurements are missing or out
+    /// of date.
+    fn analyze_pending(&mut self, roots: &[PathBuf]) {
+        let Some(analyzer) = &self.analyzer else {
+            // everything pending is queued once the pass starts
+            return;
+        };
+        match self.dir_cache.pending_analysis(roots) {
+            Ok(pending) => analyzer.queue(pending),
+            Err(e) => self.notify(Error::Index(format!(
+                "could not read the files to analyse: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Queues the files an index update added or changed.
+    fn analyze_upserted(&self, update: &IndexUpdate) {
+        if let Some(analyzer) = &self.analyzer {
+            analyzer.queue(
+                update
+                    .upserted
+                    .iter()
+                    .filter(|r| !r.is_dir)
+                    .map(|r| (r.path.clone(), r.mtime))
+                    .collect(),
+            );
+        }
+    }
+
+    /// Re-lists (or re-runs the search for) the current directory if any of
+    /// `changed` is visible in it.
+    fn refresh_file_list<'a>(
+        &mut self,
+        mut changed: impl Iterator<Item = &'a Path>,
+    ) -> Task<Message> {
+        let current_dir = self.file_selector.current_dir.clone();
+        if self.file_selector.search_value.len() > 2 {
+            let roots = self
+                .search_scope
+                .roots(&current_dir, &self.config.libraries);
+            if changed.any(|p| roots.iter().any(|(_, root)| p.starts_with(root))) {
+                let search_value = self.file_selector.search_value.clone();
+                return self.update(Message::Search(search_value));
+            }
+        } else if changed.any(|p| p.parent() == Some(current_dir.as_path())) && current_dir.is_dir()
+        {
+            if let Err(e) = self.file_selector.refresh() {
+                self.notify(e);
+            }
+            self.file_selector.attach_records(&self.dir_cache);
+        }
+        Task::none()
+    }
+
+    fn perform(&mut self, action: Action) -> Task<Message> {
+        let message = match action {
+            Action::TogglePlaying => Message::TogglePlaying,
+            Action::Stop => Message::StopPlayback,
+            Action::SeekForward => Message::SeekBy(SEEK_STEP),
+            Action::SeekBackward => Message::SeekBy(-SEEK_STEP),
+            Action::ToggleLoop => Message::ToggleLoop(!self.player.controls.looping),
+            Action::ZoomIn => Message::Zoom(1.0),
+            Action::ZoomOut => Message::Zoom(-1.0),
+            Action::ZoomToSelection => Message::ZoomToSelection,
+            Action::CycleLanes => Message::LanesSelected(self.player.controls.lanes.next()),
+            Action::ToggleSpectrogram => {
+                Message::ToggleSpectrogram(!self.player.controls.show_spectrogram)
+            }
+            Action::SelectNext => Message::MoveSelection(1),
+            Action::SelectPrevious => Message::MoveSelection(-1),
+            Action::PageDown => Message::MoveSelection(PAGE_ROWS),
+            Action::PageUp => Message::MoveSelection(-PAGE_ROWS),
+            Action::Open => Message::OpenSelected,
+            Action::GoUp => Message::GoUp,
+            Action::FocusSearch => Message::FocusSearch,
+            Action::ToggleFavorite => Message::ToggleFavorite,
+            Action::Reveal => Message::Reveal,
+            Action::ShowShortcuts => Message::ToggleShortcuts,
+        };
+        self.update(message)
+    }
+
+    /// The gain that brings `path` to the loudness target, once it has been
+    /// analysed.
+    fn loudness_gain(&self, path: &Path) -> Option<f64> {
+        let record = self.dir_cache.get(path).ok().flatten()?;
+        Some(loudness::matching_gain(
+            record.lufs?,
+            record.peak?,
+            self.config.loudness_target,
+        ))
+    }
+
+    /// Shows `error` in the notification area, and badges the file in the
+    /// list if it is about one.
+    fn notify(&mut self, error: Error) {
+        eprintln!("{}", error);
+        if let Error::Open(path, _) | Error::Decode(path, _) = &error {
+            self.file_selector
+                .broken
+                .insert(path.clone(), error.clone());
+        }
+        // the same failure tends to repeat, e.g. on every analysis batch
+        if self.notifications.last() == Some(&error) {
+            return;
+        }
+        if self.notifications.len() == MAX_NOTIFICATIONS {
+            self.notifications.remove(0);
+        }
+        self.notifications.push(error);
+    }
+
+    /// Walks `dir` in the background unless it is already indexed.
+    fn index_dir(&mut self, dir: PathBuf) -> Task<Message> {
+        if self.dir_cache.contains_key(&dir) || !self.indexing.insert(dir.clone()) {
+            return Task::none();
+        }
+        let walker = future::lazy(|_| {
+            let children = FileRecord::walk(&dir);
+            (dir, children)
+        });
+        Task::perform(walker, Message::InsertDircache)
+    }
+
     pub fn view(&self) -> Element<'_, Message> {
         let player = self.player.view();
         let menu = self.menu.view();
-        let file_selector_container = iced::widget::container(self.file_selector.view())
-            .width(Length::Fill)
-            .height(Length::Fill)
-            .center_x(Length::Fill);
+        let places = Places.view(&self.config.libraries, &self.file_selector.current_dir);
+        let settings = self.settings.as_ref().map(|settings| {
+            settings.view(
+                &self.player.output,
+                self.output_from_env,
+                &self.config.spectrogram,
+            )
+        });
+        let file_selector_container = iced::widget::container(self.file_selector.view(
+            &self.search_scope,
+            &self.config.libraries,
+            &self.config.favorites,
+        ))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x(Length::Fill);
 
-        iced::widget::column![
+        let status = self
+            .status
+            .as_ref()
+            .map(|status| iced::widget::text(status).size(14));
+        let notifications = self.notifications.iter().enumerate().map(|(i, error)| {
+            iced::widget::row![
+                iced::widget::text(error.to_string())
+                    .size(14)
+                    .style(iced::widget::text::danger)
+                    .width(Length::Fill),
+                iced::widget::button(iced::widget::text("Dismiss").size(12))
+                    .on_press(Message::DismissNotification(i))
+                    .style(iced::widget::button::text),
+            ]
+            .align_y(iced::Alignment::Center)
+            .into()
+        });
+        let analysis = self.analysis_progress.map(|(done, total)| {
+            iced::widget::row![
+                iced::widget::text(format!("Analyzing {}/{}", done, total)).size(14),
+                iced::widget::progress_bar(0.0..=total as f32, done as f32).height(6),
+            ]
+            .spacing(10)
+            .align_y(iced::Alignment::Center)
+        });
+
+        let content = iced::widget::column![
             menu,
+            iced::widget::Column::new()
+                .extend(notifications)
+                .push_maybe(status)
+                .push_maybe(analysis),
             //PaneGrid::new(&self.panes, |id, pane, is_maximized| {
             //}
-
-            file_selector_container,
+            iced::widget::row![places, file_selector_container].push_maybe(settings),
             player,
             //self.file_selector_divider_vpos,
-        ]
-        .into()
+        ];
+        if !self.show_shortcuts {
+            return content.into();
+        }
+        // clicking outside the list closes it
+        let overlay = iced::widget::mouse_area(iced::widget::center(iced::widget::opaque(
+            self.keymap.view(),
+        )))
+        .on_press(Message::ToggleShortcuts);
+        iced::widget::stack![content, overlay].into()
     }
 }
@@ -1,6 +1,8 @@
 use futures::channel::mpsc::UnboundedReceiver;
 use futures::future::Aborted;
+use iced::keyboard::{Key, Modifiers};
 use std::ffi::OsStr;
+use std::process::Command;
 
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -8,21 +10,76 @@ use std::sync::Arc;
 #[derive(Debug, Clone)]
 pub enum Message {
     SelectedFile(Option<PathBuf>),
+    /// Moves the selection by this many rows, playing the file it lands on.
+    MoveSelection(isize),
+    /// Opens the selected directory, or plays the selected file again.
+    OpenSelected,
+    /// Changes to the parent directory, selecting the one we came from.
+    GoUp,
+    FocusSearch,
+    /// A key no widget used, looked up in the keymap.
+    KeyPressed(Key, Modifiers),
+    /// Takes the keyboard focus away from the search box.
+    FocusFileList,
     ChangeDirectory(PathBuf),
     Search(String),
-    SearchCompleted(Result<Vec<PathBuf>, Aborted>),
-    InsertDircache((PathBuf, Vec<PathBuf>)),
+    SearchCompleted(Result<Vec<super::search::Hit>, Aborted>),
+    SearchScopeSelected(super::search::SearchScope),
+    InsertDircache((PathBuf, Vec<super::FileRecord>)),
+    IndexChanged(super::IndexUpdate),
     InvalidateDircache(),
+    RescanLibrary,
+    ToggleSettings,
+    OutputDevicesListed(Vec<String>),
+    OutputSelected(super::Output),
+    RescanCompleted(Result<(super::IndexUpdate, super::RescanReport), String>),
+    AnalysisProgress(super::analyzer::AnalysisProgress),
+    AnalyzerReady(super::analyzer::Analyzer),
+    SortBy(super::SortColumn),
+    WaveFormChunk(super::WaveFormChunk),
+    FindSimilar,
+    SimilarFound(Result<(PathBuf, Vec<super::search::Hit>), String>),
     Seek(f64),
     SeekCommit,
+    /// Seeks this many seconds forward, or back if negative.
+    SeekBy(f64),
+    /// Zooms the waveform in this many steps around the middle of the view,
+    /// out if negative.
+    Zoom(f32),
+    /// Zooms in this many steps around the point that far across the view.
+    ZoomAt(f32, f32),
+    /// Scrolls the waveform by this much of the view.
+    Pan(f32),
+    /// Centres the waveform on the point this far through the file.
+    ScrollTo(f32),
+    ZoomToSelection,
+    ToggleFavorite,
+    /// Shows the selected entry in the system's file manager.
+    Reveal,
+    ToggleShortcuts,
+    /// Plays from the given sample, from a click or drag on the waveform.
+    SeekTo(u64),
+    /// Loops the samples between the two, from a shift-drag on the waveform.
+    LoopRegion(u64, u64),
     PlayerMsg(
         (
             Option<super::PlayerMsg>,
             Arc<UnboundedReceiver<super::PlayerMsg>>,
         ),
     ),
+    AddLibrary,
+    RemoveLibrary(usize),
     TogglePlaying,
+    VolumeChanged(f32),
+    /// The volume slider was let go, save the volume.
+    VolumeCommit,
+    ToggleLoudnessMatch(bool),
+    ToggleLoop(bool),
+    LanesSelected(super::Lanes),
+    ToggleSpectrogram(bool),
+    SpectrogramChanged(super::Spectrogram),
     StopPlayback,
+    DismissNotification(usize),
     VResizeFileSelector(u16),
 }
 
@@ -32,6 +89,33 @@ pub fn is_audio(x: &OsStr) -> bool {
     valid_extensions.iter().any(|&s| x_str.ends_with(s))
 }
 
+/// Opens the system file manager on the directory holding `path`, with
+/// `path` selected where the file manager can do that.
+pub fn reveal(path: &Path) -> Result<(), super::Error> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    } else if cfg!(target_os = "windows") {
+        let mut select = std::ffi::OsString::from("/select,");
+        select.push(path);
+        let mut command = Command::new("explorer");
+        command.arg(select);
+        command
+    } else {
+        let mut command = Command::new("xdg-open");
+        command.arg(path.parent().unwrap_or(path));
+        command
+    };
+    command
+        .spawn()
+        .map(|mut child| {
+            // reaped so it does not linger as a zombie
+            std::thread::spawn(move || child.wait());
+        })
+        .map_err(|e| super::Error::Reveal(path.to_owned(), e.to_string()))
+}
+
 pub fn is_hidden(entry: &Path) -> bool {
     match entry.file_name() {
         Some(s) => s.to_string_lossy().starts_with('.'),
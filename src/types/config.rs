@@ -0,0 +1,101 @@
+use super::{Error, Lanes, Output, Spectrogram};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+/// User settings, kept in `tundra/config.toml` under the platform config dir.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub libraries: Vec<Library>,
+    /// Playback volume, 0 to 1.
+    pub volume: f32,
+    /// Whether previews are gained to `loudness_target`.
+    pub loudness_match: bool,
+    /// LUFS.
+    pub loudness_target: f64,
+    pub output: Output,
+    /// How the waveform lays out the channels.
+    pub lanes: Lanes,
+    /// Whether the spectrogram is shown under the waveform.
+    pub show_spectrogram: bool,
+    pub spectrogram: Spectrogram,
+    /// Keys for the actions named, see `keys::ACTIONS`. Actions left out
+    /// keep their default keys.
+    pub keybindings: BTreeMap<String, Vec<String>>,
+    pub favorites: BTreeSet<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            libraries: Vec::new(),
+            volume: 1.0,
+            loudness_match: false,
+            loudness_target: -18.0,
+            output: Output::Default,
+            lanes: Lanes::default(),
+            show_spectrogram: false,
+            spectrogram: Spectrogram::default(),
+            keybindings: BTreeMap::new(),
+            favorites: BTreeSet::new(),
+        }
+    }
+}
+
+/// A named library root shown in the places sidebar.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Library {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Library {
+    pub fn new(path: PathBuf) -> Library {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => path.to_string_lossy().into_owned(),
+        };
+        Library { name, path }
+    }
+}
+
+impl Config {
+    pub fn get_path() -> Option<PathBuf> {
+        match dirs::config_dir() {
+            Some(mut config_dir) => {
+                config_dir.push("tundra");
+                let _ = std::fs::create_dir_all(config_dir.clone());
+                config_dir.push("config");
+                config_dir.set_extension("toml");
+                Some(config_dir)
+            }
+            None => None,
+        }
+    }
+
+    pub fn load() -> Config {
+        let path = match Config::get_path() {
+            Some(path) => path,
+            None => return Config::default(),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).unwrap_or_else(|e| {
+                eprintln!("ignoring invalid {}: {}", path.display(), e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+
+    pub fn persist(&self) -> Result<(), Error> {
+        let path = match Config::get_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        toml::to_string_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|s| std::fs::write(&path, s).map_err(|e| e.to_string()))
+            .map_err(|e| Error::Persist(path, e))
+    }
+}
@@ -0,0 +1,725 @@
+use super::FileList;
+use crate::analysis::Analysis;
+use crate::analysis::key::Key;
+use rodio::Source;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+/// The sample index: one row per file or directory below an indexed root,
+/// stored in sqlite under the user's cache dir.
+pub struct DirCache {
+    conn: Connection,
+    roots: Vec<PathBuf>,
+}
+
+/// A batch of incremental changes to the index.
+#[derive(Debug, Clone, Default)]
+pub struct IndexUpdate {
+    pub removed: Vec<PathBuf>,
+    pub upserted: Vec<FileRecord>,
+}
+
+impl IndexUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.upserted.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.removed
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(self.upserted.iter().map(|r| r.path.as_path()))
+    }
+}
+
+/// The outcome of analysing one file, as measured at `mtime`.
+#[derive(Debug, Clone)]
+pub struct AnalysisResult {
+    pub path: PathBuf,
+    pub mtime: i64,
+    pub analysis: Option<Analysis>,
+}
+
+/// `MIGRATIONS[n]` upgrades the schema from `user_version` n to n + 1.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE roots (
+        path TEXT PRIMARY KEY NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE files (
+        path TEXT PRIMARY KEY NOT NULL,
+        is_dir INTEGER NOT NULL,
+        size INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        duration REAL,
+        channels INTEGER,
+        sample_rate INTEGER,
+        bit_depth INTEGER,
+        format TEXT
+    ) WITHOUT ROWID;
+    CREATE INDEX files_duration ON files (duration);
+    CREATE INDEX files_sample_rate ON files (sample_rate);
+",
+    "
+    ALTER TABLE files ADD COLUMN peak REAL;
+    ALTER TABLE files ADD COLUMN rms REAL;
+    ALTER TABLE files ADD COLUMN lufs REAL;
+    ALTER TABLE files ADD COLUMN dc_offset REAL;
+    ALTER TABLE files ADD COLUMN analyzed_mtime INTEGER;
+    CREATE INDEX files_lufs ON files (lufs);
+",
+    "
+    ALTER TABLE files ADD COLUMN bpm REAL;
+    CREATE INDEX files_bpm ON files (bpm);
+    -- analyse everything again to fill it in
+    UPDATE files SET analyzed_mtime = NULL;
+",
+    "
+    ALTER TABLE files ADD COLUMN key TEXT;
+    UPDATE files SET analyzed_mtime = NULL;
+",
+    "
+    ALTER TABLE files ADD COLUMN features BLOB;
+    UPDATE files SET analyzed_mtime = NULL;
+",
+];
+
+const COLUMNS: &str = "path, is_dir, size, mtime, duration, channels, sample_rate, bit_depth, \
+     format, peak, rms, lufs, dc_offset, analyzed_mtime, bpm, key";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Nanoseconds since the unix epoch.
+    pub mtime: i64,
+    pub duration: Option<f64>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u16>,
+    pub format: Option<String>,
+    /// True peak in dBTP, filled in by the analysis pass.
+    pub peak: Option<f64>,
+    /// dBFS.
+    pub rms: Option<f64>,
+    /// Integrated loudness.
+    pub lufs: Option<f64>,
+    pub dc_offset: Option<f64>,
+    /// The `mtime` the analysis fields were measured at; a file is analyzed
+    /// again once it no longer matches.
+    pub analyzed_mtime: Option<i64>,
+    pub bpm: Option<f64>,
+    pub key: Option<Key>,
+}
+
+impl FileRecord {
+    /// Stats `path` and, for audio files, reads the stream header.
+    pub fn scan(path: &Path) -> Option<FileRecord> {
+        let mut record = FileRecord::stat(path)?;
+        if !record.is_dir {
+            record.probe();
+        }
+        Some(record)
+    }
+
+    /// Size and mtime only; the audio fields are left empty.
+    pub fn stat(path: &Path) -> Option<FileRecord> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(FileRecord {
+            path: path.to_owned(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_nanos() as i64),
+            ..FileRecord::default()
+        })
+    }
+
+    /// Walks `root` the same way the file list filters entries.
+    pub fn walk(root: &Path) -> Vec<FileRecord> {
+        WalkDir::new(root)
+            .max_depth(100)
+            .max_open(100)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|e| FileList::file_filter(e.path().into()))
+            .filter_map(|e| match e {
+                Ok(e) => FileRecord::scan(e.path()),
+                Err(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn probe(&mut self) {
+        let ext = self
+            .path
+            .extension()
+            .map(|x| x.to_string_lossy().to_lowercase());
+        match ext.as_deref() {
+            Some("wav") => {
+                if let Ok(reader) = hound::WavReader::open(&self.path) {
+                    let spec = reader.spec();
+                    self.duration = Some(reader.duration() as f64 / spec.sample_rate as f64);
+                    self.channels = Some(spec.channels);
+                    self.sample_rate = Some(spec.sample_rate);
+                    self.bit_depth = Some(spec.bits_per_sample);
+                    self.format = Some(
+                        match spec.sample_format {
+                            hound::SampleFormat::Int => "pcm",
+                            hound::SampleFormat::Float => "float",
+                        }
+                        .to_owned(),
+                    );
+                    return;
+                }
+            }
+            Some("flac") => {
+                if let Ok(reader) = claxon::FlacReader::open(&self.path) {
+                    let info = reader.streaminfo();
+                    self.duration = info.samples.map(|n| n as f64 / info.sample_rate as f64);
+                    self.channels = Some(info.channels as u16);
+                    self.sample_rate = Some(info.sample_rate);
+                    self.bit_depth = Some(info.bits_per_sample as u16);
+                    self.format = Some("flac".to_owned());
+                    return;
+                }
+            }
+            _ => (),
+        }
+        // lossy formats have no meaningful bit depth
+        let decoder = match File::open(&self.path)
+            .ok()
+            .and_then(|f| rodio::Decoder::new(BufReader::new(f)).ok())
+        {
+            Some(decoder) => decoder,
+            None => return,
+        };
+        self.duration = decoder.total_duration().map(|d| d.as_secs_f64());
+        self.channels = Some(decoder.channels());
+        self.sample_rate = Some(decoder.sample_rate());
+        self.format = ext.map(|x| if x == "ogg" { "vorbis".to_owned() } else { x });
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<FileRecord> {
+        Ok(FileRecord {
+            path: PathBuf::from(row.get::<_, String>(0)?),
+            is_dir: row.get(1)?,
+            size: row.get::<_, i64>(2)? as u64,
+            mtime: row.get(3)?,
+            duration: row.get(4)?,
+            channels: row.get(5)?,
+            sample_rate: row.get(6)?,
+            bit_depth: row.get(7)?,
+            format: row.get(8)?,
+            peak: row.get(9)?,
+            rms: row.get(10)?,
+            lufs: row.get(11)?,
+            dc_offset: row.get(12)?,
+            analyzed_mtime: row.get(13)?,
+            bpm: row.get(14)?,
+            key: row
+                .get::<_, Option<String>>(15)?
+                .and_then(|k| Key::parse(&k)),
+        })
+    }
+}
+
+impl DirCache {
+    pub fn get_path() -> Option<PathBuf> {
+        match dirs::cache_dir() {
+            Some(mut cache_dir) => {
+                cache_dir.push("tundra");
+                let _ = std::fs::create_dir(cache_dir.clone());
+                cache_dir.push("index");
+                cache_dir.set_extension("sqlite3");
+                Some(cache_dir)
+            }
+            None => None,
+        }
+    }
+
+    /// The bincode blob older versions kept all indexed paths in.
+    fn legacy_path() -> Option<PathBuf> {
+        DirCache::get_path().map(|p| p.with_file_name("dir_cache.bin"))
+    }
+
+    pub fn open() -> rusqlite::Result<DirCache> {
+        let conn = match DirCache::get_path() {
+            Some(path) => Connection::open(path)?,
+            None => Connection::open_in_memory()?,
+        };
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        let mut dir_cache = DirCache {
+            conn,
+            roots: Vec::new(),
+        };
+        dir_cache.migrate()?;
+        dir_cache.load_roots()?;
+        Ok(dir_cache)
+    }
+
+    pub fn get_dir_cache() -> DirCache {
+        match DirCache::open() {
+            Ok(mut dir_cache) => {
+                dir_cache.import_legacy();
+                dir_cache
+            }
+            Err(e) => {
+                eprintln!(
+                    "could not open the sample index, using a temporary one: {}",
+                    e
+                );
+                DirCache::in_memory().expect("in-memory sqlite")
+            }
+        }
+    }
+
+    fn in_memory() -> rusqlite::Result<DirCache> {
+        let mut dir_cache = DirCache {
+            conn: Connection::open_in_memory()?,
+            roots: Vec::new(),
+        };
+        dir_cache.migrate()?;
+        Ok(dir_cache)
+    }
+
+    fn migrate(&mut self) -> rusqlite::Result<()> {
+        let version: usize = self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            eprintln!(
+                "sample index has schema version {}, newer than this build ({})",
+                version,
+                MIGRATIONS.len()
+            );
+            return Ok(());
+        }
+        let tx = self.conn.transaction()?;
+        for migration in &MIGRATIONS[version..] {
+            tx.execute_batch(migration)?;
+        }
+        tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        tx.commit()
+    }
+
+    /// Moves the paths from an old `dir_cache.bin` into the index. Metadata
+    /// is left empty and gets filled in by the next scan of each root.
+    fn import_legacy(&mut self) {
+        let legacy = match DirCache::legacy_path() {
+            Some(p) if p.exists() => p,
+            _ => return,
+        };
+        let old: HashMap<PathBuf, Vec<PathBuf>> = std::fs::read(&legacy)
+            .ok()
+            .and_then(|s| bincode::deserialize(&s).ok())
+            .unwrap_or_default();
+        for (root, children) in old {
+            let records: Vec<FileRecord> = children
+                .into_iter()
+                .map(|path| FileRecord {
+                    is_dir: path.is_dir(),
+                    path,
+                    ..FileRecord::default()
+                })
+                .collect();
+            match self.insert(root, records) {
+                // the index cannot hold it, and trying again will not help
+                Ok(()) | Err(rusqlite::Error::InvalidPath(_)) => (),
+                Err(e) => {
+                    eprintln!("could not import {}: {}", legacy.display(), e);
+                    return;
+                }
+            }
+        }
+        let _ = std::fs::remove_file(legacy);
+    }
+
+    /// Replaces everything indexed below `root` with `records` and marks
+    /// `root` as indexed.
+    pub fn insert(&mut self, root: PathBuf, records: Vec<FileRecord>) -> rusqlite::Result<()> {
+        // paths are stored as text, and a root that is not would never count
+        // as indexed
+        let root = match root.to_str() {
+            Some(root) => root.to_owned(),
+            None => return Err(rusqlite::Error::InvalidPath(root)),
+        };
+        let tx = self.conn.transaction()?;
+        let (lo, hi) = subtree_bounds(&root);
+        tx.execute(
+            "DELETE FROM files WHERE path > ?1 AND path < ?2",
+            params![lo, hi],
+        )?;
+        tx.execute(
+            "DELETE FROM roots WHERE path > ?1 AND path < ?2",
+            params![lo, hi],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO roots (path) VALUES (?1)",
+            params![root],
+        )?;
+        upsert(&tx, &records)?;
+        tx.commit()?;
+        self.load_roots()
+    }
+
+    /// Applies incremental changes without touching the rest of the index.
+    /// Removing a directory removes everything below it.
+    pub fn apply(&mut self, update: &IndexUpdate) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        for path in update.removed.iter().filter_map(|p| p.to_str()) {
+            let (lo, hi) = subtree_bounds(path);
+            tx.execute(
+                "DELETE FROM files WHERE path = ?1 OR (path > ?2 AND path < ?3)",
+                params![path, lo, hi],
+            )?;
+        }
+        upsert(&tx, &update.upserted)?;
+        tx.commit()
+    }
+
+    /// Audio files below `roots` whose analysis is missing or older than the
+    /// file.
+    pub fn pending_analysis(&self, roots: &[PathBuf]) -> rusqlite::Result<Vec<(PathBuf, i64)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, mtime FROM files
+             WHERE path > ?1 AND path < ?2 AND is_dir = 0 AND analyzed_mtime IS NOT mtime
+             ORDER BY path",
+        )?;
+        let mut pending = Vec::new();
+        for root in roots {
+            // nested roots are covered by the one they are in
+            if roots.iter().any(|r| r != root && root.starts_with(r)) {
+                continue;
+            }
+            let Some(root) = root.to_str() else {
+                continue;
+            };
+            let (lo, hi) = subtree_bounds(root);
+            let rows = stmt.query_map(params![lo, hi], |row| {
+                Ok((PathBuf::from(row.get::<_, String>(0)?), row.get(1)?))
+            })?;
+            for row in rows {
+                pending.push(row?);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Stores analysis results, skipping files that changed since they were
+    /// queued. A `None` result marks the file as undecodable so it is not
+    /// retried until it changes.
+    pub fn store_analysis(&mut self, results: &[AnalysisResult]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "UPDATE files SET duration = coalesce(?3, duration), peak = ?4, rms = ?5,
+                 lufs = ?6, dc_offset = ?7, bpm = ?8, key = ?9, features = ?10,
+                 analyzed_mtime = ?2
+                 WHERE path = ?1 AND mtime = ?2",
+            )?;
+            for result in results {
+                let path = match result.path.to_str() {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let a = result.analysis.as_ref();
+                stmt.execute(params![
+                    path,
+                    result.mtime,
+                    a.map(|a| a.duration),
+                    a.map(|a| a.peak),
+                    a.map(|a| a.rms),
+                    a.map(|a| a.lufs),
+                    a.map(|a| a.dc_offset),
+                    a.and_then(|a| a.bpm),
+                    a.and_then(|a| a.key).map(|k| k.to_string()),
+                    a.map(|a| encode_features(&a.features)),
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Every analysed file below `dir` with its timbre features.
+    pub fn features_under(&self, dir: &Path) -> rusqlite::Result<Vec<(FileRecord, Vec<f32>)>> {
+        let dir = match dir.to_str() {
+            Some(dir) => dir,
+            None => return Ok(Vec::new()),
+        };
+        let (lo, hi) = subtree_bounds(dir);
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {}, features FROM files
+             WHERE path > ?1 AND path < ?2 AND features IS NOT NULL",
+            COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![lo, hi], |row| {
+            let features: Vec<u8> = row.get(16)?;
+            Ok((FileRecord::from_row(row)?, decode_features(&features)))
+        })?;
+        rows.collect()
+    }
+
+    pub fn features(&self, path: &Path) -> rusqlite::Result<Option<Vec<f32>>> {
+        let path = match path.to_str() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let features: Option<Option<Vec<u8>>> = self
+            .conn
+            .prepare_cached("SELECT features FROM files WHERE path = ?1")?
+            .query_row(params![path], |row| row.get(0))
+            .optional()?;
+        Ok(features.flatten().map(|f| decode_features(&f)))
+    }
+
+    pub fn clear(&mut self) -> rusqlite::Result<()> {
+        self.conn
+            .execute_batch("DELETE FROM files; DELETE FROM roots;")?;
+        self.roots.clear();
+        Ok(())
+    }
+
+    fn load_roots(&mut self) -> rusqlite::Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT path FROM roots ORDER BY path")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0).map(PathBuf::from))?;
+        self.roots = rows.collect::<rusqlite::Result<_>>()?;
+        Ok(())
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Whether `dir` lies inside an indexed root.
+    pub fn contains_key(&self, dir: &Path) -> bool {
+        self.roots.iter().any(|root| dir.starts_with(root))
+    }
+
+    pub fn get(&self, path: &Path) -> rusqlite::Result<Option<FileRecord>> {
+        let path = match path.to_str() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        self.conn
+            .prepare_cached(&format!("SELECT {} FROM files WHERE path = ?1", COLUMNS))?
+            .query_row(params![path], FileRecord::from_row)
+            .optional()
+    }
+
+    /// Every indexed entry strictly below `dir`, in path order.
+    pub fn entries_under(&self, dir: &Path) -> rusqlite::Result<Vec<FileRecord>> {
+        let dir = match dir.to_str() {
+            Some(dir) => dir,
+            None => return Ok(Vec::new()),
+        };
+        let (lo, hi) = subtree_bounds(dir);
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM files WHERE path > ?1 AND path < ?2 ORDER BY path",
+            COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![lo, hi], FileRecord::from_row)?;
+        rows.collect()
+    }
+}
+
+fn upsert(conn: &Connection, records: &[FileRecord]) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "INSERT OR REPLACE INTO files ({}) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+        COLUMNS
+    ))?;
+    for r in records {
+        let path = match r.path.to_str() {
+            Some(path) => path,
+            None => continue,
+        };
+        stmt.execute(params![
+            path,
+            r.is_dir,
+            r.size as i64,
+            r.mtime,
+            r.duration,
+            r.channels,
+            r.sample_rate,
+            r.bit_depth,
+            r.format,
+            r.peak,
+            r.rms,
+            r.lufs,
+            r.dc_offset,
+            r.analyzed_mtime,
+            r.bpm,
+            r.key.map(|k| k.to_string()),
+        ])?;
+    }
+    Ok(())
+}
+
+fn encode_features(features: &[f32]) -> Vec<u8> {
+    features.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn decode_features(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Exclusive string bounds containing exactly the paths below `dir`, so
+/// subtree queries can use the primary key index.
+fn subtree_bounds(dir: &str) -> (String, String) {
+    let dir = dir.trim_end_matches(MAIN_SEPARATOR);
+    let next = (MAIN_SEPARATOR as u8 + 1) as char;
+    (
+        format!("{}{}", dir, MAIN_SEPARATOR),
+        format!("{}{}", dir, next),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, mtime: i64, analyzed_mtime: Option<i64>) -> FileRecord {
+        FileRecord {
+            path: PathBuf::from(path),
+            mtime,
+            analyzed_mtime,
+            ..FileRecord::default()
+        }
+    }
+
+    #[test]
+    fn only_analyses_below_the_given_roots() {
+        let mut dir_cache = DirCache::in_memory().unwrap();
+        dir_cache
+            .insert(
+                PathBuf::from("/samples"),
+                vec![
+                    FileRecord {
+                        path: PathBuf::from("/samples/drums"),
+                        is_dir: true,
+                        ..FileRecord::default()
+                    },
+                    file("/samples/drums/kick.wav", 1, None),
+                    file("/samples/drums/snare.wav", 2, Some(2)),
+                    file("/samples/drums/hat.wav", 3, Some(1)),
+                    file("/samples/keys/pad.wav", 4, None),
+                ],
+            )
+            .unwrap();
+        dir_cache
+            .insert(
+                PathBuf::from("/downloads"),
+                vec![file("/downloads/loop.wav", 5, None)],
+            )
+            .unwrap();
+        let roots = [
+            PathBuf::from("/samples/drums"),
+            PathBuf::from("/samples/drums/808"),
+            PathBuf::from("/samples/drumsets"),
+        ];
+        let pending = dir_cache.pending_analysis(&roots).unwrap();
+        assert_eq!(
+            pending,
+            [
+                (PathBuf::from("/samples/drums/hat.wav"), 3),
+                (PathBuf::from("/samples/drums/kick.wav"), 1),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_roots_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let mut dir_cache = DirCache::in_memory().unwrap();
+        let root = PathBuf::from(std::ffi::OsStr::from_bytes(b"/samples/\xff"));
+        assert!(dir_cache.insert(root.clone(), Vec::new()).is_err());
+        assert!(!dir_cache.contains_key(&root));
+    }
+
+    /// An index at schema `version`, with a root and an analysed file in it.
+    fn index_at(version: usize) -> DirCache {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..version] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", version).unwrap();
+        if version > 0 {
+            conn.execute_batch(
+                "INSERT INTO roots (path) VALUES ('/samples');
+                 INSERT INTO files (path, is_dir, size, mtime, duration)
+                 VALUES ('/samples/kick.wav', 0, 100, 7, 0.5);",
+            )
+            .unwrap();
+        }
+        if version > 1 {
+            conn.execute_batch("UPDATE files SET analyzed_mtime = 7, lufs = -12.0")
+                .unwrap();
+        }
+        DirCache {
+            conn,
+            roots: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn migrates_from_every_schema_version() {
+        for version in 0..=MIGRATIONS.len() {
+            let mut dir_cache = index_at(version);
+            dir_cache.migrate().unwrap();
+            dir_cache.load_roots().unwrap();
+            let current: usize = dir_cache
+                .conn
+                .pragma_query_value(None, "user_version", |row| row.get(0))
+                .unwrap();
+            assert_eq!(current, MIGRATIONS.len(), "from version {}", version);
+            let record = dir_cache.get(Path::new("/samples/kick.wav")).unwrap();
+            if version == 0 {
+                assert!(dir_cache.roots().is_empty());
+                assert_eq!(record, None);
+                continue;
+            }
+            assert_eq!(dir_cache.roots(), [PathBuf::from("/samples")]);
+            let record = record.unwrap();
+            assert_eq!(record.duration, Some(0.5), "from version {}", version);
+            // columns added since call for the file to be analysed again
+            let analyzed = (version == MIGRATIONS.len()).then_some(7);
+            assert_eq!(record.analyzed_mtime, analyzed, "from version {}", version);
+            assert_eq!(record.lufs, (version > 1).then_some(-12.0));
+            let pending = dir_cache.pending_analysis(dir_cache.roots()).unwrap();
+            assert_eq!(pending.len(), 1 - analyzed.is_some() as usize);
+            assert_eq!(dir_cache.features(&record.path).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn leaves_newer_indexes_alone() {
+        let mut dir_cache = index_at(MIGRATIONS.len());
+        dir_cache
+            .conn
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        dir_cache.migrate().unwrap();
+        let version: usize = dir_cache
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() + 1);
+    }
+}
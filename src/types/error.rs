@@ -0,0 +1,52 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Failures that are reported in the notification area instead of ending
+/// the program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The file could not be opened.
+    Open(PathBuf, String),
+    /// The file is not audio we can decode.
+    Decode(PathBuf, String),
+    /// The directory could not be listed.
+    ListDir(PathBuf, String),
+    /// No audio output device could be opened.
+    Output(String),
+    /// The file manager could not be started.
+    Reveal(PathBuf, String),
+    /// The settings could not be saved.
+    Persist(PathBuf, String),
+    /// Reading or writing the index failed; the message says what was
+    /// being done.
+    Index(String),
+}
+
+impl Error {
+    /// The error without the path, for places that already show it.
+    pub fn reason(&self) -> &str {
+        match self {
+            Error::Open(_, reason)
+            | Error::Decode(_, reason)
+            | Error::ListDir(_, reason)
+            | Error::Output(reason)
+            | Error::Reveal(_, reason)
+            | Error::Persist(_, reason)
+            | Error::Index(reason) => reason,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Open(path, e) => write!(f, "could not open {}: {}", path.display(), e),
+            Error::Decode(path, e) => write!(f, "could not decode {}: {}", path.display(), e),
+            Error::ListDir(path, e) => write!(f, "could not list {}: {}", path.display(), e),
+            Error::Output(e) => write!(f, "no audio output: {}", e),
+            Error::Reveal(path, e) => write!(f, "could not show {}: {}", path.display(), e),
+            Error::Persist(path, e) => write!(f, "could not save {}: {}", path.display(), e),
+            Error::Index(e) => f.write_str(e),
+        }
+    }
+}
@@ -1,5 +1,12 @@
 pub use super::common::*;
-use iced::widget::scrollable;
+use super::search::SearchScope;
+use super::{DirCache, Error, FileRecord, Library};
+use iced::Color;
+use iced::Element;
+use iced::Font;
+use iced::Length;
+use iced::Task;
+use iced::Theme;
 use iced::widget::Button;
 use iced::widget::Column;
 use iced::widget::Container;
@@ -7,9 +14,14 @@ use iced::widget::Row;
 use iced::widget::Svg;
 use iced::widget::Text;
 use iced::widget::TextInput;
-use iced::Element;
-use iced::Length;
+use iced::widget::button;
+use iced::widget::pick_list;
+use iced::widget::scrollable;
+use iced::widget::text::{Rich, Span};
+use iced::widget::text_input;
+use iced::widget::tooltip;
 use std::cmp::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
@@ -20,6 +32,11 @@ pub struct FileSelector {
     pub file_list: Vec<FileButton>,
     pub selected_file: Option<usize>,
     pub search_value: String,
+    pub search_error: Option<String>,
+    /// `None` keeps the natural order: by path, or by relevance for searches.
+    pub sort: Option<Sort>,
+    /// Files that failed to open or decode, shown with a badge.
+    pub broken: HashMap<PathBuf, Error>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,10 +44,122 @@ pub struct FileList {
     pub files: Vec<PathBuf>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct FileButton {
     pub file_path: PathBuf,
     pub label: String,
+    /// Char indices into `label` matched by the current search.
+    pub highlights: Vec<usize>,
+    /// What the index knows about the file, if it is indexed.
+    pub record: Option<FileRecord>,
+}
+
+/// The columns of the file list, each of which it can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    Name,
+    Duration,
+    Channels,
+    Bpm,
+    Key,
+    Loudness,
+    Peak,
+    Rms,
+    DcOffset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub column: SortColumn,
+    pub descending: bool,
+}
+
+const COLUMN_WIDTH: f32 = 72.0;
+
+fn search_id() -> text_input::Id {
+    text_input::Id::new("search")
+}
+
+fn list_id() -> scrollable::Id {
+    scrollable::Id::new("file-list")
+}
+
+impl SortColumn {
+    const METADATA: [SortColumn; 8] = [
+        SortColumn::Duration,
+        SortColumn::Channels,
+        SortColumn::Bpm,
+        SortColumn::Key,
+        SortColumn::Loudness,
+        SortColumn::Peak,
+        SortColumn::Rms,
+        SortColumn::DcOffset,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            SortColumn::Name => "Name",
+            SortColumn::Duration => "Length",
+            SortColumn::Channels => "Ch",
+            SortColumn::Bpm => "BPM",
+            SortColumn::Key => "Key",
+            SortColumn::Loudness => "LUFS",
+            SortColumn::Peak => "Peak",
+            SortColumn::Rms => "RMS",
+            SortColumn::DcOffset => "DC",
+        }
+    }
+
+    fn value(self, record: &FileRecord) -> Option<f64> {
+        match self {
+            SortColumn::Name => None,
+            SortColumn::Duration => record.duration,
+            SortColumn::Channels => record.channels.map(f64::from),
+            SortColumn::Bpm => record.bpm,
+            // around the Camelot wheel, so neighbouring keys sort together
+            SortColumn::Key => record.key.map(|k| {
+                let (number, letter) = k.camelot();
+                number as f64 + if letter == 'B' { 0.5 } else { 0.0 }
+            }),
+            SortColumn::Loudness => record.lufs,
+            SortColumn::Peak => record.peak,
+            SortColumn::Rms => record.rms,
+            SortColumn::DcOffset => record.dc_offset,
+        }
+    }
+
+    fn format(self, record: &FileRecord) -> String {
+        match (self, self.value(record)) {
+            (SortColumn::Key, _) => record.key.map_or_else(String::new, |k| k.to_string()),
+            (_, None) => String::new(),
+            (SortColumn::Duration, Some(secs)) if secs < 60.0 => format!("{:.2}s", secs),
+            (SortColumn::Duration, Some(secs)) => {
+                format!("{}:{:02}", secs as u64 / 60, secs as u64 % 60)
+            }
+            (SortColumn::Channels, Some(1.0)) => "Mono".to_owned(),
+            (SortColumn::Channels, Some(2.0)) => "Stereo".to_owned(),
+            (SortColumn::Channels, Some(channels)) => format!("{}", channels),
+            (SortColumn::DcOffset, Some(dc)) => format!("{:.2}%", dc * 100.0),
+            (SortColumn::Bpm, Some(bpm)) => format!("{:.1}", bpm),
+            (_, Some(db)) => format!("{:.1}", db),
+        }
+    }
+
+    /// Entries without a value go last whichever way the list is sorted.
+    fn compare(self, a: &FileButton, b: &FileButton, descending: bool) -> Ordering {
+        if self == SortColumn::Name {
+            let ord = a.file_path.cmp(&b.file_path);
+            return if descending { ord.reverse() } else { ord };
+        }
+        let value = |b: &FileButton| b.record.as_ref().and_then(|r| self.value(r));
+        match (value(a), value(b)) {
+            (Some(x), Some(y)) if descending => y.total_cmp(&x),
+            (Some(x), Some(y)) => x.total_cmp(&y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
 }
 
 pub struct DirUp;
@@ -63,9 +192,12 @@ impl FileList {
     }
     pub fn list_dir(
         dir: &Path,
-    ) -> std::iter::FilterMap<
-        std::fs::ReadDir,
-        fn(x: std::io::Result<std::fs::DirEntry>) -> Option<std::fs::DirEntry>,
+    ) -> Result<
+        std::iter::FilterMap<
+            std::fs::ReadDir,
+            fn(x: std::io::Result<std::fs::DirEntry>) -> Option<std::fs::DirEntry>,
+        >,
+        Error,
     > {
         fn the_filter(x: std::io::Result<std::fs::DirEntry>) -> Option<std::fs::DirEntry> {
             match x {
@@ -79,77 +211,297 @@ impl FileList {
                 Err(_) => None,
             }
         }
-        fs::read_dir(dir).unwrap().filter_map(the_filter)
+        fs::read_dir(dir)
+            .map(|entries| entries.filter_map(the_filter as fn(_) -> _))
+            .map_err(|e| Error::ListDir(dir.to_owned(), e.to_string()))
     }
 
-    pub fn new(dir: &Path) -> Vec<FileButton> {
-        let mut buttons: Vec<FileButton> = FileList::list_dir(dir)
+    pub fn new(dir: &Path) -> Result<Vec<FileButton>, Error> {
+        let mut buttons: Vec<FileButton> = FileList::list_dir(dir)?
             .map(|x| FileButton::new(x.path(), dir))
             .collect();
         buttons.sort();
-        buttons
+        Ok(buttons)
     }
 }
 
 impl FileSelector {
+    /// An empty selector at `dir`; `change_dir` lists it.
     pub fn new(dir: &Path) -> Self {
         FileSelector {
             current_dir: dir.to_owned(),
-            file_list: FileList::new(dir),
+            file_list: Vec::new(),
             selected_file: None,
             search_value: String::new(),
+            search_error: None,
+            sort: None,
+            broken: HashMap::new(),
         }
     }
 
-    pub fn view(&self) -> Column<Message> {
-        //let selected_file = self.selected_file.as_ref();
+    /// Shows `dir`, keeping the sort order. Stays where it is if `dir` cannot
+    /// be listed.
+    pub fn change_dir(&mut self, dir: &Path) -> Result<(), Error> {
+        let file_list = FileList::new(dir)?;
+        *self = FileSelector {
+            file_list,
+            sort: self.sort,
+            broken: std::mem::take(&mut self.broken),
+            ..FileSelector::new(dir)
+        };
+        Ok(())
+    }
+
+    /// Looks up entries the index has not been asked about yet and applies
+    /// the sort order. Call after replacing `file_list`.
+    pub fn attach_records(&mut self, dir_cache: &DirCache) {
+        for button in self.file_list.iter_mut().filter(|b| b.record.is_none()) {
+            button.record = dir_cache.get(&button.file_path).ok().flatten();
+        }
+        self.apply_sort();
+    }
+
+    /// Reloads the index metadata of the `changed` entries.
+    pub fn update_records(&mut self, dir_cache: &DirCache, changed: &HashSet<&Path>) {
+        for button in &mut self.file_list {
+            if changed.contains(button.file_path.as_path()) {
+                button.record = dir_cache.get(&button.file_path).ok().flatten();
+            }
+        }
+        self.apply_sort();
+    }
+
+    /// Sorts by `column`, or flips the direction if it is already sorted by it.
+    pub fn sort_by(&mut self, column: SortColumn) {
+        self.sort = Some(match self.sort {
+            Some(sort) if sort.column == column => Sort {
+                column,
+                descending: !sort.descending,
+            },
+            // the loudest or longest files are usually the ones wanted first
+            _ => Sort {
+                column,
+                descending: column != SortColumn::Name,
+            },
+        });
+        self.apply_sort();
+    }
+
+    fn apply_sort(&mut self) {
+        let sort = match self.sort {
+            Some(sort) => sort,
+            None => return,
+        };
+        let selected = self
+            .selected_file
+            .and_then(|i| self.file_list.get(i))
+            .map(|b| b.file_path.clone());
+        self.file_list
+            .sort_by(|a, b| sort.column.compare(a, b, sort.descending));
+        self.selected_file =
+            selected.and_then(|p| self.file_list.iter().position(|b| b.file_path == p));
+    }
+
+    fn header(&self) -> Row<'_, Message> {
+        let title = |column: SortColumn| match self.sort {
+            Some(sort) if sort.column == column => {
+                format!(
+                    "{} {}",
+                    column.title(),
+                    if sort.descending { "↓" } else { "↑" }
+                )
+            }
+            _ => column.title().to_owned(),
+        };
+        let button = |column: SortColumn| {
+            Button::new(Text::new(title(column)).size(16))
+                .on_press(Message::SortBy(column))
+                .style(iced::widget::button::text)
+        };
+        SortColumn::METADATA.iter().fold(
+            Row::new().push(button(SortColumn::Name).width(Length::Fill)),
+            |row, &column| row.push(button(column).width(Length::Fixed(COLUMN_WIDTH))),
+        )
+    }
+
+    pub fn selected(&self) -> Option<&FileButton> {
+        self.selected_file.and_then(|i| self.file_list.get(i))
+    }
+
+    /// Moves the selection `by` rows, stopping at either end. With nothing
+    /// selected, down starts at the top and up at the bottom.
+    pub fn move_selection(&mut self, by: isize) -> Option<&FileButton> {
+        let last = self.file_list.len().checked_sub(1)?;
+        let i = match self.selected_file {
+            Some(i) => i.saturating_add_signed(by).min(last),
+            None if by < 0 => last,
+            None => 0,
+        };
+        self.selected_file = Some(i);
+        self.file_list.get(i)
+    }
+
+    /// Selects the entry for `path`, if it is listed.
+    pub fn select(&mut self, path: &Path) {
+        self.selected_file = self.file_list.iter().position(|b| b.file_path == path);
+    }
+
+    /// Scrolls the list so the selection is in view.
+    pub fn scroll_to_selected(&self) -> Task<Message> {
+        let i = match self.selected_file {
+            Some(i) => i,
+            None => return Task::none(),
+        };
+        // row `i` of `n` is in view at this fraction whatever the row height
+        let y = i as f32 / self.file_list.len().saturating_sub(1).max(1) as f32;
+        scrollable::snap_to(list_id(), scrollable::RelativeOffset { x: 0.0, y })
+    }
+
+    pub fn focus_search() -> Task<Message> {
+        text_input::focus(search_id())
+    }
+
+    pub fn unfocus_search() -> Task<Message> {
+        // nothing has this id, so focusing it leaves nothing focused
+        text_input::focus(text_input::Id::new("file-list"))
+    }
+
+    /// Re-reads the directory, keeping the selection on the same file.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let file_list = FileList::new(&self.current_dir)?;
+        self.replace_files(file_list);
+        Ok(())
+    }
+
+    /// Shows `files` instead, keeping the selection on the same file if it
+    /// is among them.
+    pub fn replace_files(&mut self, files: Vec<FileButton>) {
+        let selected = self
+            .selected_file
+            .and_then(|i| self.file_list.get(i))
+            .map(|b| b.file_path.clone());
+        self.file_list = files;
+        self.selected_file =
+            selected.and_then(|p| self.file_list.iter().position(|b| b.file_path == p));
+    }
+
+    pub fn view<'a>(
+        &'a self,
+        scope: &SearchScope,
+        libraries: &[Library],
+        favorites: &BTreeSet<PathBuf>,
+    ) -> Column<'a, Message> {
         let dir_up =
             Container::new(DirUp.view(self.current_dir.to_owned()).padding(5)).width(Length::Fill);
         let new_col: Vec<Element<Message>> = self
             .file_list
             .iter()
             .enumerate()
-            .map(|(_i, button)| {
-                let element: Button<Message> = button.view();
-                let container = Container::new(element.padding(10)).width(Length::Fill);
-                //if Some(&i) == selected_file {
-                //    container = container.style(super::theme::Container::SelectedContainer);
-                //}
-                container.into()
+            .map(|(i, button)| {
+                let favorite = favorites.contains(&button.file_path);
+                let mut element: Button<Message> =
+                    button.view(self.broken.get(&button.file_path), favorite);
+                if Some(i) == self.selected_file {
+                    element = element.style(selected_style);
+                }
+                Container::new(element.padding(10))
+                    .width(Length::Fill)
+                    .into()
             })
             .collect();
         let fs_column = Column::with_children(new_col).spacing(0).padding(0);
-        let fs = scrollable(fs_column).height(Length::Fill);
+        let fs = scrollable(fs_column).id(list_id()).height(Length::Fill);
         let search = TextInput::new("Search", &self.search_value)
+            .id(search_id())
             .on_input(Message::Search)
+            .on_submit(Message::FocusFileList)
             //.style(super::theme::TextInput::FileSearch)
             .size(32)
             .padding(10);
+        let scope = pick_list(
+            SearchScope::options(libraries),
+            Some(scope.clone()),
+            Message::SearchScopeSelected,
+        )
+        .text_size(20)
+        .padding(12);
+        let selected = self.selected();
+        let find_similar = Button::new(Text::new("Find similar").size(20))
+            .on_press_maybe(selected.map(|_| Message::FindSimilar))
+            .style(iced::widget::button::secondary)
+            .padding(12);
+        let search_row = Row::new()
+            .push(search)
+            .push(scope)
+            .push(find_similar)
+            .align_y(iced::Alignment::Center);
 
-        Column::new().push(dir_up).push(fs).push(search)
+        let column = Column::new()
+            .push(dir_up)
+            .push(self.header().padding([0, 10]))
+            .push(fs)
+            .push(search_row);
+        match &self.search_error {
+            Some(e) => column.push(Text::new(e).size(16).style(iced::widget::text::danger)),
+            None => column,
+        }
     }
 }
 
 impl FileButton {
     pub fn new(x: PathBuf, base_path: &Path) -> Self {
-        let fp = remove_prefix(x.to_str().unwrap(), base_path.as_os_str().to_str().unwrap());
+        let path = x.to_string_lossy();
+        let fp = remove_prefix(&path, &base_path.to_string_lossy());
         let mut label = String::with_capacity(2 + fp.len());
         label.push_str("  ");
         label.push_str(fp);
         FileButton {
             file_path: x,
             label,
+            highlights: Vec::new(),
+            record: None,
         }
     }
 
-    pub fn view(&self) -> Button<Message> {
-        let text = Text::new(&self.label).size(24);
+    pub fn with_record(mut self, record: FileRecord) -> Self {
+        self.record = Some(record);
+        self
+    }
+
+    pub fn with_highlights(mut self, indices: &[usize]) -> Self {
+        // indices are relative to the path, the label is indented by two spaces
+        self.highlights = indices.iter().map(|i| i + 2).collect();
+        self
+    }
+
+    fn text(&self) -> Element<'_, Message> {
+        if self.highlights.is_empty() {
+            return Text::new(&self.label).size(24).into();
+        }
+        let mut spans: Vec<Span<Message>> = Vec::new();
+        let mut run = String::new();
+        let mut run_highlighted = false;
+        let mut highlights = self.highlights.iter().peekable();
+        for (i, c) in self.label.chars().enumerate() {
+            let highlighted = highlights.next_if_eq(&&i).is_some();
+            if highlighted != run_highlighted && !run.is_empty() {
+                spans.push(label_span(std::mem::take(&mut run), run_highlighted));
+            }
+            run_highlighted = highlighted;
+            run.push(c);
+        }
+        spans.push(label_span(run, run_highlighted));
+        Rich::with_spans(spans).size(24).into()
+    }
+
+    pub fn view(&self, error: Option<&Error>, favorite: bool) -> Button<'_, Message> {
+        let text = self.text();
         let label = Row::with_children(if self.file_path.is_dir() {
             vec![
                 Svg::from_path("./resources/folder-solid.svg")
                     .width(Length::Fixed(24.0))
                     .into(),
-                text.into(),
+                text,
             ]
         } else if is_audio(self.file_path.as_os_str()) {
             vec![
@@ -157,18 +509,52 @@ impl FileButton {
                     .height(Length::Fixed(24.0))
                     .width(Length::Shrink)
                     .into(),
-                text.into(),
+                text,
             ]
         } else {
-            vec![text.into()]
+            vec![text]
+        });
+        let columns = SortColumn::METADATA.iter().map(|&column| {
+            let value = self
+                .record
+                .as_ref()
+                .map_or_else(String::new, |r| column.format(r));
+            Text::new(value)
+                .size(16)
+                .width(Length::Fixed(COLUMN_WIDTH))
+                .into()
         });
-        Button::new(label)
+        let badge = error.map(|e| {
+            tooltip(
+                Text::new("!").size(20).style(iced::widget::text::danger),
+                Container::new(Text::new(e.reason().to_owned()).size(14))
+                    .padding(6)
+                    .style(iced::widget::container::rounded_box),
+                tooltip::Position::Top,
+            )
+        });
+        let star = favorite.then(|| Text::new("★").size(20));
+        let row = Row::new()
+            .push(label.width(Length::Fill))
+            .push_maybe(star)
+            .push_maybe(badge)
+            .extend(columns)
+            .align_y(iced::Alignment::Center);
+        Button::new(row)
             //.style(super::theme::Button::FileButton)
             .on_press(Message::SelectedFile(Some(self.file_path.to_owned())))
             .width(Length::Fill)
     }
 }
 
+impl PartialEq for FileButton {
+    fn eq(&self, other: &Self) -> bool {
+        self.file_path == other.file_path
+    }
+}
+
+impl Eq for FileButton {}
+
 impl PartialOrd for FileButton {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -181,9 +567,35 @@ impl Ord for FileButton {
     }
 }
 
+fn selected_style(theme: &Theme, status: button::Status) -> button::Style {
+    let palette = theme.extended_palette();
+    button::Style {
+        background: Some(palette.primary.strong.color.into()),
+        border: iced::Border {
+            color: palette.background.base.text,
+            width: 2.0,
+            radius: 2.0.into(),
+        },
+        ..button::primary(theme, status)
+    }
+}
+
+fn label_span<'a>(text: String, highlighted: bool) -> Span<'a, Message> {
+    let span = Span::new(text);
+    if highlighted {
+        span.color(Color::from_rgb8(0xff, 0xd0, 0x4a)).font(Font {
+            weight: iced::font::Weight::Bold,
+            ..Font::DEFAULT
+        })
+    } else {
+        span
+    }
+}
+
 fn remove_prefix<'a>(s: &'a str, prefix: &str) -> &'a str {
     match s.strip_prefix(prefix) {
-        Some(s) => unsafe { s.get_unchecked(1..s.len()) },
+        // a root prefix like `/` already ends in the separator
+        Some(s) => s.strip_prefix(std::path::MAIN_SEPARATOR).unwrap_or(s),
         None => s,
     }
 }
@@ -0,0 +1,449 @@
+pub use super::common::*;
+use iced::keyboard::key::Named;
+use iced::keyboard::{Key, Modifiers};
+use iced::widget::{Button, Column, Container, Row, Text};
+use iced::{Element, Length};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Things a key can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    TogglePlaying,
+    Stop,
+    SeekForward,
+    SeekBackward,
+    ToggleLoop,
+    ZoomIn,
+    ZoomOut,
+    ZoomToSelection,
+    CycleLanes,
+    ToggleSpectrogram,
+    SelectNext,
+    SelectPrevious,
+    PageDown,
+    PageUp,
+    Open,
+    GoUp,
+    FocusSearch,
+    ToggleFavorite,
+    Reveal,
+    ShowShortcuts,
+}
+
+/// Every action with its name in the config file, what it does and its
+/// default keys.
+const ACTIONS: [(Action, &str, &str, &[&str]); 20] = [
+    (
+        Action::TogglePlaying,
+        "toggle_playing",
+        "Play / pause",
+        &["Space"],
+    ),
+    (Action::Stop, "stop", "Stop", &["S"]),
+    (
+        Action::SeekForward,
+        "seek_forward",
+        "Seek forward",
+        &["Right"],
+    ),
+    (
+        Action::SeekBackward,
+        "seek_backward",
+        "Seek back",
+        &["Left"],
+    ),
+    (Action::ToggleLoop, "toggle_loop", "Loop", &["L"]),
+    (Action::ZoomIn, "zoom_in", "Zoom in", &["+", "="]),
+    (Action::ZoomOut, "zoom_out", "Zoom out", &["-"]),
+    (
+        Action::ZoomToSelection,
+        "zoom_to_selection",
+        "Zoom to selection",
+        &["Z"],
+    ),
+    (
+        Action::CycleLanes,
+        "cycle_lanes",
+        "Channels / summed / mid-side",
+        &["C"],
+    ),
+    (
+        Action::ToggleSpectrogram,
+        "toggle_spectrogram",
+        "Spectrogram",
+        &["V"],
+    ),
+    (Action::SelectNext, "select_next", "Next file", &["Down"]),
+    (
+        Action::SelectPrevious,
+        "select_previous",
+        "Previous file",
+        &["Up"],
+    ),
+    (Action::PageDown, "page_down", "Down a page", &["PageDown"]),
+    (Action::PageUp, "page_up", "Up a page", &["PageUp"]),
+    (
+        Action::Open,
+        "open",
+        "Open directory / play again",
+        &["Enter"],
+    ),
+    (Action::GoUp, "go_up", "Parent directory", &["Backspace"]),
+    (
+        Action::FocusSearch,
+        "focus_search",
+        "Search",
+        &["/", "Ctrl+F"],
+    ),
+    (
+        Action::ToggleFavorite,
+        "toggle_favorite",
+        "Favorite",
+        &["F"],
+    ),
+    (Action::Reveal, "reveal", "Show in file manager", &["R"]),
+    (
+        Action::ShowShortcuts,
+        "show_shortcuts",
+        "Keyboard shortcuts",
+        &["?", "F1"],
+    ),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .find(|(_, n, _, _)| *n == name)
+            .map(|(action, _, _, _)| *action)
+    }
+
+    fn entry(self) -> &'static (Action, &'static str, &'static str, &'static [&'static str]) {
+        // every action has an entry
+        ACTIONS.iter().find(|(a, _, _, _)| *a == self).unwrap()
+    }
+
+    pub fn description(self) -> &'static str {
+        self.entry().2
+    }
+}
+
+/// A key with the modifiers held, written like `Ctrl+Shift+Left` or `?`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    key: Key,
+    ctrl: bool,
+    alt: bool,
+    logo: bool,
+    shift: bool,
+}
+
+/// Names for the non-character keys that can be bound.
+const NAMED_KEYS: [(&str, Named); 26] = [
+    ("Space", Named::Space),
+    ("Enter", Named::Enter),
+    ("Backspace", Named::Backspace),
+    ("Tab", Named::Tab),
+    ("Escape", Named::Escape),
+    ("Delete", Named::Delete),
+    ("Left", Named::ArrowLeft),
+    ("Right", Named::ArrowRight),
+    ("Up", Named::ArrowUp),
+    ("Down", Named::ArrowDown),
+    ("PageUp", Named::PageUp),
+    ("PageDown", Named::PageDown),
+    ("Home", Named::Home),
+    ("End", Named::End),
+    ("F1", Named::F1),
+    ("F2", Named::F2),
+    ("F3", Named::F3),
+    ("F4", Named::F4),
+    ("F5", Named::F5),
+    ("F6", Named::F6),
+    ("F7", Named::F7),
+    ("F8", Named::F8),
+    ("F9", Named::F9),
+    ("F10", Named::F10),
+    ("F11", Named::F11),
+    ("F12", Named::F12),
+];
+
+impl Chord {
+    pub fn parse(s: &str) -> Option<Chord> {
+        // `+` on its own, or at the end, is the key rather than a separator
+        let (modifiers, key) = match s.strip_suffix('+') {
+            Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest, "+"),
+            _ => match s.rsplit_once('+') {
+                Some((modifiers, key)) => (modifiers, key),
+                None => ("", s),
+            },
+        };
+        let mut chord = Chord {
+            key: parse_key(key)?,
+            ctrl: false,
+            alt: false,
+            logo: false,
+            shift: false,
+        };
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => chord.ctrl = true,
+                "alt" | "option" => chord.alt = true,
+                "super" | "cmd" | "logo" => chord.logo = true,
+                "shift" => chord.shift = true,
+                _ => return None,
+            }
+        }
+        // `matches` ignores shift for characters, so this would also fire
+        // without it
+        if chord.shift && matches!(chord.key, Key::Character(_)) {
+            return None;
+        }
+        Some(chord)
+    }
+
+    /// Shift only counts for named keys; for characters it is already part
+    /// of the character, so `?` matches whether or not it needs shift.
+    fn matches(&self, key: &Key, modifiers: Modifiers) -> bool {
+        let key = match key.as_ref() {
+            Key::Character(c) => Key::Character(c.to_lowercase().into()),
+            _ => key.clone(),
+        };
+        let shift = matches!(key, Key::Character(_)) || self.shift == modifiers.shift();
+        self.key == key
+            && self.ctrl == modifiers.control()
+            && self.alt == modifiers.alt()
+            && self.logo == modifiers.logo()
+            && shift
+    }
+}
+
+fn parse_key(key: &str) -> Option<Key> {
+    if let Some((_, named)) = NAMED_KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+    {
+        return Some(Key::Named(*named));
+    }
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(Key::Character(c.to_lowercase().to_string().into())),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.ctrl, "Ctrl+"),
+            (self.alt, "Alt+"),
+            (self.logo, "Super+"),
+            (self.shift, "Shift+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        match &self.key {
+            Key::Named(named) => match NAMED_KEYS.iter().find(|(_, n)| n == named) {
+                Some((name, _)) => f.write_str(name),
+                None => write!(f, "{:?}", named),
+            },
+            Key::Character(c) => f.write_str(&c.to_uppercase()),
+            Key::Unidentified => f.write_str("?"),
+        }
+    }
+}
+
+/// The active bindings: the defaults, with the actions the config file
+/// names bound to its keys instead. Keys the config file binds are taken
+/// out of the other actions' defaults.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Action, Vec<Chord>)>,
+}
+
+impl Keymap {
+    /// Unknown actions and keys in `config` are reported and skipped, as
+    /// are keys it binds to more than one action, which keep the first.
+    pub fn new(config: &BTreeMap<String, Vec<String>>) -> Keymap {
+        for name in config.keys().filter(|n| Action::from_name(n).is_none()) {
+            eprintln!("ignoring key binding for unknown action {}", name);
+        }
+        let mut taken: Vec<(Chord, &str)> = Vec::new();
+        let configured: Vec<Option<Vec<Chord>>> = ACTIONS
+            .iter()
+            .map(|&(_, name, _, _)| {
+                let keys = config.get(name)?;
+                let mut chords = Vec::new();
+                for key in keys {
+                    let Some(chord) = Chord::parse(key) else {
+                        eprintln!("ignoring invalid key {} for {}", key, name);
+                        continue;
+                    };
+                    match taken.iter().find(|(c, _)| *c == chord) {
+                        Some((_, other)) if *other != name => {
+                            eprintln!(
+                                "ignoring key {} for {}, it is bound to {}",
+                                key, name, other
+                            );
+                        }
+                        Some(_) => (),
+                        None => {
+                            taken.push((chord.clone(), name));
+                            chords.push(chord);
+                        }
+                    }
+                }
+                Some(chords)
+            })
+            .collect();
+        let bindings = ACTIONS
+            .iter()
+            .zip(configured)
+            .map(|(&(action, _, _, defaults), chords)| {
+                let chords = chords.unwrap_or_else(|| {
+                    defaults
+                        .iter()
+                        .filter_map(|key| Chord::parse(key))
+                        .filter(|chord| taken.iter().all(|(c, _)| c != chord))
+                        .collect()
+                });
+                (action, chords)
+            })
+            .collect();
+        Keymap { bindings }
+    }
+
+    pub fn action(&self, key: &Key, modifiers: Modifiers) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, chords)| chords.iter().any(|c| c.matches(key, modifiers)))
+            .map(|(action, _)| *action)
+    }
+
+    /// Bound actions, in the order they are listed.
+    pub fn bindings(&self) -> impl Iterator<Item = (Action, &[Chord])> {
+        self.bindings
+            .iter()
+            .filter(|(_, chords)| !chords.is_empty())
+            .map(|(action, chords)| (*action, chords.as_slice()))
+    }
+}
+
+impl Keymap {
+    /// The keyboard shortcuts overlay.
+    pub fn view(&self) -> Element<'_, Message> {
+        let rows = self.bindings().map(|(action, chords)| {
+            let keys: Vec<String> = chords.iter().map(Chord::to_string).collect();
+            Row::new()
+                .push(
+                    Text::new(keys.join(", "))
+                        .size(16)
+                        .width(Length::Fixed(140.0)),
+                )
+                .push(Text::new(action.description()).size(16))
+                .into()
+        });
+        let close = Button::new(Text::new("Close").size(14))
+            .on_press(Message::ToggleShortcuts)
+            .style(iced::widget::button::secondary);
+        let column = Column::new()
+            .push(Text::new("Keyboard shortcuts").size(20))
+            .extend(rows)
+            .push(close)
+            .spacing(6);
+        Container::new(column)
+            .padding(16)
+            .style(iced::widget::container::rounded_box)
+            .into()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::new(&BTreeMap::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bindings: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        bindings
+            .iter()
+            .map(|(name, keys)| {
+                (
+                    name.to_string(),
+                    keys.iter().map(|k| k.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_plus_as_a_key() {
+        let plus = Chord::parse("+").unwrap();
+        assert_eq!(plus.key, Key::Character("+".into()));
+        assert!(!plus.ctrl);
+        let ctrl_plus = Chord::parse("Ctrl++").unwrap();
+        assert_eq!(ctrl_plus.key, Key::Character("+".into()));
+        assert!(ctrl_plus.ctrl && !ctrl_plus.shift);
+        assert_eq!(ctrl_plus.to_string(), "Ctrl++");
+    }
+
+    #[test]
+    fn parses_modifiers_and_named_keys() {
+        let chord = Chord::parse("Shift+Left").unwrap();
+        assert_eq!(chord.key, Key::Named(Named::ArrowLeft));
+        assert!(chord.shift && !chord.ctrl && !chord.alt && !chord.logo);
+        assert_eq!(chord.to_string(), "Shift+Left");
+        assert_eq!(Chord::parse("ctrl+f"), Chord::parse("Control+F"));
+        assert_eq!(Chord::parse("Hyper+F"), None);
+        assert_eq!(Chord::parse("Space+"), None);
+        assert_eq!(Chord::parse("Foo"), None);
+        assert_eq!(Chord::parse("Shift+S"), None);
+    }
+
+    #[test]
+    fn shift_only_counts_for_named_keys() {
+        let left = Chord::parse("Shift+Left").unwrap();
+        assert!(left.matches(&Key::Named(Named::ArrowLeft), Modifiers::SHIFT));
+        assert!(!left.matches(&Key::Named(Named::ArrowLeft), Modifiers::empty()));
+        let question = Chord::parse("?").unwrap();
+        assert!(question.matches(&Key::Character("?".into()), Modifiers::SHIFT));
+    }
+
+    #[test]
+    fn config_keys_override_other_defaults() {
+        let keymap = Keymap::new(&config(&[("stop", &["Space"])]));
+        let space = Key::Named(Named::Space);
+        assert_eq!(
+            keymap.action(&space, Modifiers::empty()),
+            Some(Action::Stop)
+        );
+        let toggle = keymap
+            .bindings()
+            .find(|(action, _)| *action == Action::TogglePlaying);
+        assert!(toggle.is_none());
+        let spaces = keymap
+            .bindings()
+            .flat_map(|(_, chords)| chords)
+            .filter(|c| c.key == space)
+            .count();
+        assert_eq!(spaces, 1);
+    }
+
+    #[test]
+    fn conflicting_config_keys_keep_the_first_action() {
+        let keymap = Keymap::new(&config(&[("reveal", &["X"]), ("stop", &["X", "S"])]));
+        let x = Key::Character("x".into());
+        assert_eq!(keymap.action(&x, Modifiers::empty()), Some(Action::Stop));
+        let reveal = keymap
+            .bindings()
+            .find(|(action, _)| *action == Action::Reveal);
+        assert!(reveal.is_none());
+    }
+}
@@ -25,7 +25,10 @@ fn menu_1<'a>() -> Element<'a, Message> {
     let root = menu_bar!((
         debug_button_s("Menu"),
         menu_tpl_1(menu_items!(
+            (debug_button_s("Rescan library").on_press(Message::RescanLibrary))
             (debug_button_s("Invalidate cache").on_press(Message::InvalidateDircache()))
+            (debug_button_s("Settings").on_press(Message::ToggleSettings))
+            (debug_button_s("Keyboard shortcuts").on_press(Message::ToggleShortcuts))
         ))
     ))
     .width(110);
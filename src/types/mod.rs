@@ -1,12 +1,35 @@
+mod analyzer;
 mod app;
 mod common;
+mod config;
+mod dir_cache;
+mod error;
 mod file_selector;
+mod keys;
 mod menu;
+mod output;
+mod peaks;
+mod places;
 mod player;
+mod rescan;
 pub mod search;
+mod settings;
+mod similar;
+mod spectrogram;
+mod watcher;
 mod waveform;
 
 pub use app::*;
+pub use config::*;
+pub use dir_cache::*;
+pub use error::*;
 pub use file_selector::*;
+pub use keys::*;
 pub use menu::*;
+pub use output::*;
+pub use peaks::*;
+pub use places::*;
 pub use player::*;
+pub use rescan::*;
+pub use settings::*;
+pub use spectrogram::*;
@@ -0,0 +1,391 @@
+use hound::WavWriter;
+use rodio::Source;
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::queue::SourcesQueueOutput;
+use rodio::source::UniformSourceIterator;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Overrides the configured output, e.g. `null`, `file:/tmp/out.wav`,
+/// `render:/tmp/out.wav` or `device:Speakers`.
+const OUTPUT_VAR: &str = "TUNDRA_OUTPUT";
+
+/// The format headless outputs render in.
+const HEADLESS_CHANNELS: u16 = 2;
+const HEADLESS_SAMPLE_RATE: u32 = 44100;
+/// Frames rendered per wake-up of a headless output.
+const HEADLESS_BLOCK: u64 = 1024;
+
+/// Where the player sends its audio.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// The system's default device.
+    #[default]
+    Default,
+    /// The device with this name.
+    Device(String),
+    /// Nowhere, at the pace of a real device. For machines without a sound
+    /// card.
+    Null,
+    /// A 32 bit float wav file, overwritten for each file played.
+    File(PathBuf),
+    /// Like `File`, but written as fast as the audio can be rendered rather
+    /// than at the pace of a real device.
+    Render(PathBuf),
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Default => f.write_str("System default"),
+            Output::Device(name) => f.write_str(name),
+            Output::Null => f.write_str("None"),
+            Output::File(path) => write!(f, "File: {}", path.display()),
+            Output::Render(path) => write!(f, "Render: {}", path.display()),
+        }
+    }
+}
+
+/// Keeps an output running until dropped.
+pub struct Stream {
+    _device: Option<rodio::OutputStream>,
+    headless: Option<Headless>,
+}
+
+struct Headless {
+    stop: Arc<AtomicBool>,
+    /// Set while the sink is being cleared, which waits for its sounds to
+    /// be read even if it is paused.
+    clearing: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Stream {
+    /// Empties `sink`, the sink opened with this stream, and pauses it.
+    pub fn clear(&self, sink: &rodio::Sink) {
+        match &self.headless {
+            Some(headless) => {
+                headless.clearing.store(true, Ordering::Relaxed);
+                sink.clear();
+                headless.clearing.store(false, Ordering::Relaxed);
+            }
+            None => sink.clear(),
+        }
+    }
+}
+
+impl Drop for Headless {
+    /// Waits for the output thread, so a file is complete once its stream
+    /// is gone.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Output {
+    /// The output named by `TUNDRA_OUTPUT`, if it is set.
+    pub fn from_env() -> Option<Output> {
+        let value = std::env::var(OUTPUT_VAR).ok()?;
+        match value.split_once(':') {
+            Some(("file", path)) => Some(Output::File(PathBuf::from(path))),
+            Some(("render", path)) => Some(Output::Render(PathBuf::from(path))),
+            Some(("device", name)) => Some(Output::Device(name.to_owned())),
+            _ if value == "null" => Some(Output::Null),
+            _ if value == "default" => Some(Output::Default),
+            _ => {
+                eprintln!("ignoring {}={}", OUTPUT_VAR, value);
+                None
+            }
+        }
+    }
+
+    /// Opens the output and a sink playing to it.
+    pub fn open(&self) -> Result<(Stream, Arc<rodio::Sink>), String> {
+        let device = match self {
+            Output::Default => rodio::OutputStream::try_default(),
+            Output::Device(name) => {
+                let device = rodio::cpal::default_host()
+                    .output_devices()
+                    .map_err(|e| e.to_string())?
+                    .find(|d| d.name().is_ok_and(|n| &n == name))
+                    .ok_or_else(|| format!("there is no device named {}", name))?;
+                rodio::OutputStream::try_from_device(&device)
+            }
+            Output::Null => return headless(None, true),
+            Output::File(path) => return headless(Some(path), true),
+            Output::Render(path) => return headless(Some(path), false),
+        };
+        let (stream, handle) = device.map_err(|e| e.to_string())?;
+        let sink = rodio::Sink::try_new(&handle).map_err(|e| e.to_string())?;
+        Ok((
+            Stream {
+                _device: Some(stream),
+                headless: None,
+            },
+            Arc::new(sink),
+        ))
+    }
+}
+
+/// Names of the output devices of the default host.
+pub fn devices() -> Vec<String> {
+    match rodio::cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            eprintln!("could not list output devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// A sink drained by a thread of its own instead of a sound card, writing
+/// what it plays to `path` if given.
+fn headless(path: Option<&Path>, paced: bool) -> Result<(Stream, Arc<rodio::Sink>), String> {
+    let spec = hound::WavSpec {
+        channels: HEADLESS_CHANNELS,
+        sample_rate: HEADLESS_SAMPLE_RATE,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = match path {
+        Some(path) => Some(hound::WavWriter::create(path, spec).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let (sink, queue) = rodio::Sink::new_idle();
+    let sink = Arc::new(sink);
+    let playing = Arc::clone(&sink);
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+    let clearing = Arc::new(AtomicBool::new(false));
+    let draining = Arc::clone(&clearing);
+    let thread = thread::spawn(move || {
+        render(queue, &playing, &stopped, &draining, paced, &mut writer);
+        if let Some(Err(e)) = writer.map(hound::WavWriter::finalize) {
+            eprintln!("headless output: {}", e);
+        }
+    });
+    Ok((
+        Stream {
+            _device: None,
+            headless: Some(Headless {
+                stop,
+                clearing,
+                thread: Some(thread),
+            }),
+        },
+        sink,
+    ))
+}
+
+/// Plays `queue`, the output of `sink`, into `writer` until `stopped`.
+/// Nothing is rendered while the sink is paused or has nothing to play, but
+/// a paused sink is read from, and what it gives thrown away, while it is
+/// `clearing`.
+fn render(
+    queue: SourcesQueueOutput<f32>,
+    sink: &rodio::Sink,
+    stopped: &AtomicBool,
+    clearing: &AtomicBool,
+    paced: bool,
+    writer: &mut Option<WavWriter<BufWriter<File>>>,
+) {
+    let block = Duration::from_secs_f64(HEADLESS_BLOCK as f64 / HEADLESS_SAMPLE_RATE as f64);
+    let idle = || sink.empty() || (sink.is_paused() && !clearing.load(Ordering::Relaxed));
+    while idle() {
+        if stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        thread::sleep(block);
+    }
+    let mut samples: UniformSourceIterator<_, f32> =
+        UniformSourceIterator::new(Primed::new(queue), HEADLESS_CHANNELS, HEADLESS_SAMPLE_RATE);
+    // when playback last started, and the frames rendered since
+    let mut pace: Option<(Instant, u64)> = None;
+    while !stopped.load(Ordering::Relaxed) {
+        let mut rendered = 0;
+        while rendered < HEADLESS_BLOCK && !idle() {
+            let paused = sink.is_paused();
+            for _ in 0..HEADLESS_CHANNELS {
+                let sample = samples.next().unwrap_or(0.0);
+                if !paused
+                    && let Some(w) = writer
+                    && let Err(e) = w.write_sample(sample)
+                {
+                    eprintln!("headless output: {}", e);
+                    *writer = None;
+                }
+            }
+            rendered += 1;
+        }
+        if rendered == 0 {
+            pace = None;
+            thread::sleep(block);
+        } else if paced {
+            // keep to the pace of a real device
+            let (start, total) = pace.get_or_insert_with(|| (Instant::now(), 0));
+            *total += rendered;
+            let due = Duration::from_secs_f64(*total as f64 / HEADLESS_SAMPLE_RATE as f64);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// A sink's queue with the first frame of the first sound read ahead. The
+/// queue only takes on the format of a sound once it is read from, and
+/// `UniformSourceIterator` looks at the format before reading.
+struct Primed {
+    queue: SourcesQueueOutput<f32>,
+    first: Vec<f32>,
+    pos: usize,
+}
+
+impl Primed {
+    fn new(mut queue: SourcesQueueOutput<f32>) -> Primed {
+        let mut first: Vec<f32> = queue.next().into_iter().collect();
+        while first.len() < queue.channels() as usize
+            && let Some(sample) = queue.next()
+        {
+            first.push(sample);
+        }
+        Primed {
+            queue,
+            first,
+            pos: 0,
+        }
+    }
+}
+
+impl Iterator for Primed {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        match self.first.get(self.pos) {
+            Some(&sample) => {
+                self.pos += 1;
+                Some(sample)
+            }
+            None => self.queue.next(),
+        }
+    }
+}
+
+impl Source for Primed {
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.first.len() - self.pos {
+            0 => self.queue.current_frame_len(),
+            left => Some(left),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.queue.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.queue.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::decoded::{Decoded, DecodedSource};
+    use rodio::buffer::SamplesBuffer;
+
+    /// Waits for `sink` to finish, for at most a few seconds.
+    fn wait_for(sink: &rodio::Sink) {
+        let start = Instant::now();
+        while !sink.empty() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn written(path: &Path) -> Vec<f32> {
+        let samples = hound::WavReader::open(path)
+            .unwrap()
+            .into_samples::<f32>()
+            .map(Result::unwrap)
+            .collect();
+        std::fs::remove_file(path).unwrap();
+        samples
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tundra-output-{}-{}.wav", std::process::id(), name))
+    }
+
+    #[test]
+    fn renders_what_is_played_to_a_file() {
+        let path = temp_file("played");
+        let (stream, sink) = Output::Render(path.clone()).open().unwrap();
+        // a tenth of a second of stereo at the output's own rate
+        let played: Vec<i16> = (0..8820).map(|i| (i % 200 - 100) * 300).collect();
+        let audio = Decoded::spawn(SamplesBuffer::new(2, 44100, played.clone()));
+        sink.append(DecodedSource::new(audio, 0));
+        wait_for(&sink);
+        // nothing more is written once the sink has run dry
+        thread::sleep(Duration::from_millis(50));
+        drop(stream);
+        let samples = written(&path);
+        assert!(samples.len() >= played.len(), "{}", samples.len());
+        assert!(samples.len() <= played.len() + 2, "{}", samples.len());
+        for (&sample, &expected) in samples.iter().zip(&played) {
+            assert!((sample - expected as f32 / 32768.0).abs() < 1e-4);
+        }
+        assert!(samples[played.len()..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn renders_nothing_while_paused() {
+        let path = temp_file("paused");
+        let (stream, sink) = Output::Render(path.clone()).open().unwrap();
+        sink.pause();
+        let audio = Decoded::spawn(SamplesBuffer::new(2, 44100, vec![16384i16; 8820]));
+        sink.append(DecodedSource::new(audio, 0));
+        thread::sleep(Duration::from_millis(50));
+        sink.play();
+        wait_for(&sink);
+        drop(stream);
+        // silence rendered while paused would come first
+        let samples = written(&path);
+        assert!(samples.len() >= 8820, "{}", samples.len());
+        assert!(samples.len() <= 8822, "{}", samples.len());
+        assert!(samples[..8820].iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn seeks_and_stops_while_paused() {
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let (stream, sink) = Output::Null.open().unwrap();
+            let audio = Decoded::spawn(SamplesBuffer::new(2, 44100, vec![16384i16; 88200]));
+            sink.append(DecodedSource::new(Arc::clone(&audio), 0));
+            thread::sleep(Duration::from_millis(20));
+            sink.pause();
+            // a seek while paused, then a stop
+            stream.clear(&sink);
+            sink.append(DecodedSource::new(audio, 22050));
+            stream.clear(&sink);
+            done_tx.send(sink.empty()).unwrap();
+        });
+        let emptied = done_rx.recv_timeout(Duration::from_secs(5));
+        assert_eq!(emptied, Ok(true));
+    }
+}
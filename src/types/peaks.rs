@@ -0,0 +1,389 @@
+use super::{DirCache, FileRecord};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Samples per bucket of the finest level.
+const BASE: usize = 64;
+/// Each level has buckets this many times the size of the one below.
+const FACTOR: usize = 4;
+/// Enough that the coarsest buckets hold about 16M samples.
+const LEVELS: usize = 10;
+/// Part of the cache file names, so files in an older layout are not read.
+const CACHE_VERSION: u32 = 3;
+/// Past this many bytes of cache files, the least recently used go.
+const CACHE_LIMIT: u64 = 256 << 20;
+
+/// The extremes and power of a run of samples, full scale being 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub min: f32,
+    pub max: f32,
+    /// Mean square.
+    pub power: f32,
+}
+
+impl Bucket {
+    /// Empty buckets have `min` above `max`.
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+
+    pub fn rms(&self) -> f32 {
+        self.power.sqrt()
+    }
+}
+
+/// Builds a `Bucket` out of samples and smaller buckets.
+struct Accumulator {
+    min: f32,
+    max: f32,
+    squares: f64,
+    count: usize,
+}
+
+impl Accumulator {
+    fn new() -> Accumulator {
+        Accumulator {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            squares: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add_samples(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            self.min = self.min.min(sample);
+            self.max = self.max.max(sample);
+            self.squares += sample as f64 * sample as f64;
+        }
+        self.count += samples.len();
+    }
+
+    /// Adds `bucket`, which covers `count` samples.
+    fn add_bucket(&mut self, bucket: &Bucket, count: usize) {
+        self.min = self.min.min(bucket.min);
+        self.max = self.max.max(bucket.max);
+        self.squares += bucket.power as f64 * count as f64;
+        self.count += count;
+    }
+
+    fn finish(&self) -> Bucket {
+        Bucket {
+            min: self.min,
+            max: self.max,
+            power: (self.squares / self.count.max(1) as f64) as f32,
+        }
+    }
+}
+
+/// Min, max and RMS levels of a waveform at several resolutions, so that
+/// drawing any part of it at any width looks at about the same number of
+/// buckets.
+#[derive(Debug, Clone)]
+pub struct Peaks {
+    /// Samples folded in so far.
+    pub len: usize,
+    /// `levels[n]` has buckets of `BASE * FACTOR^n` samples. Only whole
+    /// buckets are kept.
+    levels: Vec<Vec<Bucket>>,
+    /// The samples of the base bucket being filled.
+    partial: Vec<f32>,
+}
+
+/// What is written to the cache: the base level of each of a file's
+/// signals, and what they were made from.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    mtime: i64,
+    len: usize,
+    signals: Vec<Vec<Bucket>>,
+}
+
+impl Peaks {
+    pub fn new() -> Peaks {
+        Peaks {
+            len: 0,
+            levels: vec![Vec::new(); LEVELS],
+            partial: Vec::with_capacity(BASE),
+        }
+    }
+
+    fn bucket_size(level: usize) -> usize {
+        BASE * FACTOR.pow(level as u32)
+    }
+
+    pub fn extend(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            self.partial.push(sample);
+            if self.partial.len() == BASE {
+                let mut bucket = Accumulator::new();
+                bucket.add_samples(&self.partial);
+                self.partial.clear();
+                self.push_bucket(bucket.finish());
+            }
+        }
+        self.len += samples.len();
+    }
+
+    /// Adds a whole base bucket, and the buckets above it that it completes.
+    fn push_bucket(&mut self, bucket: Bucket) {
+        self.levels[0].push(bucket);
+        for level in 1..LEVELS {
+            let below = &self.levels[level - 1];
+            if !below.len().is_multiple_of(FACTOR) {
+                break;
+            }
+            let mut merged = Accumulator::new();
+            for bucket in &below[below.len() - FACTOR..] {
+                merged.add_bucket(bucket, 1);
+            }
+            self.levels[level].push(merged.finish());
+        }
+    }
+
+    /// The bucket covering samples `start..end`, from the coarsest level no
+    /// coarser than `level`. `samples` fills in below the base level where
+    /// buckets do not line up with the range, or where they are not there
+    /// yet. Past the end of `samples`, the base buckets the range falls in
+    /// stand in for them.
+    pub fn range(&self, level: usize, start: usize, end: usize, samples: &[f32]) -> Bucket {
+        let mut bucket = Accumulator::new();
+        self.range_into(level.min(LEVELS - 1), start, end, samples, &mut bucket);
+        bucket.finish()
+    }
+
+    fn range_into(
+        &self,
+        level: usize,
+        start: usize,
+        end: usize,
+        samples: &[f32],
+        out: &mut Accumulator,
+    ) {
+        if start >= end {
+            return;
+        }
+        let size = Peaks::bucket_size(level);
+        let buckets = &self.levels[level];
+        let first = start.div_ceil(size);
+        let last = (end / size).min(buckets.len());
+        if first >= last {
+            self.below(level, start, end, samples, out);
+            return;
+        }
+        self.below(level, start, first * size, samples, out);
+        for bucket in &buckets[first..last] {
+            out.add_bucket(bucket, size);
+        }
+        self.below(level, last * size, end, samples, out);
+    }
+
+    fn below(
+        &self,
+        level: usize,
+        start: usize,
+        end: usize,
+        samples: &[f32],
+        out: &mut Accumulator,
+    ) {
+        if level > 0 {
+            return self.range_into(level - 1, start, end, samples, out);
+        }
+        let covered = end.min(samples.len());
+        if start < covered {
+            out.add_samples(&samples[start..covered]);
+        }
+        let start = start.max(covered);
+        if start >= end {
+            return;
+        }
+        let base = &self.levels[0];
+        let first = start / BASE;
+        let last = end.div_ceil(BASE).min(base.len());
+        for (i, bucket) in base.iter().enumerate().take(last).skip(first) {
+            let overlap = end.min((i + 1) * BASE) - start.max(i * BASE);
+            out.add_bucket(bucket, overlap);
+        }
+        // the samples not in a whole bucket yet
+        let whole = base.len() * BASE;
+        let end = end.saturating_sub(whole).min(self.partial.len());
+        let start = start.saturating_sub(whole);
+        if start < end {
+            out.add_samples(&self.partial[start..end]);
+        }
+    }
+
+    /// The coarsest level with buckets no wider than `samples_per_pixel`.
+    pub fn level_for(samples_per_pixel: f32) -> usize {
+        (0..LEVELS)
+            .rev()
+            .find(|&level| Peaks::bucket_size(level) as f32 <= samples_per_pixel)
+            .unwrap_or(0)
+    }
+
+    fn cache_file(path: &Path) -> Option<PathBuf> {
+        let mut key = path.as_os_str().as_encoded_bytes().to_vec();
+        key.extend(CACHE_VERSION.to_le_bytes());
+        let name = format!("{:016x}.bin", fnv1a(&key));
+        Some(Peaks::cache_dir()?.join(name))
+    }
+
+    /// Next to the index.
+    fn cache_dir() -> Option<PathBuf> {
+        Some(DirCache::get_path()?.with_file_name("peaks"))
+    }
+
+    /// The cached peaks of the signals of `path`, if they were made from it
+    /// as it is now.
+    pub fn load(path: &Path) -> Option<Vec<Peaks>> {
+        let record = FileRecord::stat(path)?;
+        let cache_file = Peaks::cache_file(path)?;
+        let bytes = fs::read(&cache_file).ok()?;
+        let entry: CacheEntry = bincode::deserialize(&bytes).ok()?;
+        if entry.path != path || entry.size != record.size || entry.mtime != record.mtime {
+            return None;
+        }
+        // the modification time marks when the entry was last used
+        let _ = fs::File::options()
+            .write(true)
+            .open(&cache_file)
+            .and_then(|f| f.set_modified(SystemTime::now()));
+        let signals = entry
+            .signals
+            .into_iter()
+            .map(|base| {
+                let mut peaks = Peaks::new();
+                for bucket in base {
+                    peaks.push_bucket(bucket);
+                }
+                peaks.len = entry.len;
+                peaks
+            })
+            .collect();
+        Some(signals)
+    }
+
+    /// Caches the peaks of the signals of `path`, which should be complete.
+    pub fn save(path: &Path, signals: &[Peaks]) -> Result<(), String> {
+        let record = FileRecord::stat(path).ok_or("the file is gone")?;
+        let cache_file = Peaks::cache_file(path).ok_or("there is no cache directory")?;
+        let entry = CacheEntry {
+            path: path.to_owned(),
+            size: record.size,
+            mtime: record.mtime,
+            len: signals.first().map_or(0, |peaks| peaks.len),
+            signals: signals
+                .iter()
+                .map(|peaks| peaks.levels[0].clone())
+                .collect(),
+        };
+        let bytes = bincode::serialize(&entry).map_err(|e| e.to_string())?;
+        let dir = cache_file.parent().ok_or("there is no cache directory")?;
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        fs::write(&cache_file, bytes).map_err(|e| e.to_string())?;
+        trim_cache(dir, CACHE_LIMIT).map_err(|e| e.to_string())
+    }
+
+    pub fn clear_cache() -> Result<(), String> {
+        match Peaks::cache_dir() {
+            Some(dir) if dir.exists() => fs::remove_dir_all(dir).map_err(|e| e.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` gives the same cache file names
+/// from one build to the next.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Removes the least recently used files in `dir` until they add up to at
+/// most `limit` bytes.
+fn trim_cache(dir: &Path, limit: u64) -> io::Result<()> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+    }
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    files.sort();
+    for (_, len, path) in files {
+        if total <= limit {
+            break;
+        }
+        fs::remove_file(path)?;
+        total -= len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn falls_back_to_base_buckets_without_samples() {
+        let samples: Vec<f32> = (0..BASE * 2 + 10).map(|i| i as f32 / 1000.0).collect();
+        let mut peaks = Peaks::new();
+        peaks.extend(&samples);
+        // inside the second bucket, which stands in for all of it
+        let bucket = peaks.range(0, BASE + 3, BASE + 5, &[]);
+        assert_eq!(
+            (bucket.min, bucket.max),
+            (samples[BASE], samples[BASE * 2 - 1])
+        );
+        assert_eq!(
+            peaks.range(0, BASE + 3, BASE + 5, &samples).max,
+            samples[BASE + 4]
+        );
+        // the tail is not in a bucket yet
+        let tail = peaks.range(0, BASE * 2 + 2, BASE * 2 + 20, &[]);
+        assert_eq!(
+            (tail.min, tail.max),
+            (samples[BASE * 2 + 2], samples[BASE * 2 + 9])
+        );
+        assert!(peaks.range(0, BASE * 3, BASE * 3 + 1, &[]).is_empty());
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn trims_least_recently_used_first() {
+        let dir = std::env::temp_dir().join(format!("tundra-peaks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["old", "used", "new"].iter().enumerate() {
+            let file = dir.join(name);
+            fs::write(&file, [0; 100]).unwrap();
+            let age = Duration::from_secs([30, 10, 20][i]);
+            let file = fs::File::options().write(true).open(&file).unwrap();
+            file.set_modified(now - age).unwrap();
+        }
+        trim_cache(&dir, 250).unwrap();
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(left, ["new", "used"]);
+    }
+}
@@ -0,0 +1,52 @@
+use super::Library;
+pub use super::common::*;
+use iced::widget::{Button, Column, Container, Row, Svg, Text, scrollable};
+use iced::{Element, Length};
+use std::path::Path;
+
+/// The sidebar of library roots.
+pub struct Places;
+
+impl Places {
+    pub fn view<'a>(&self, libraries: &'a [Library], current_dir: &Path) -> Element<'a, Message> {
+        let header = Text::new("Places").size(20);
+        let entries = libraries.iter().enumerate().map(|(i, library)| {
+            let active = current_dir.starts_with(&library.path);
+            let open = Button::new(
+                Row::new()
+                    .push(
+                        Svg::from_path("./resources/folder-solid.svg")
+                            .width(Length::Fixed(16.0))
+                            .height(Length::Fixed(16.0)),
+                    )
+                    .push(Text::new(&library.name).size(18))
+                    .spacing(6),
+            )
+            .on_press(Message::ChangeDirectory(library.path.clone()))
+            .style(if active {
+                iced::widget::button::primary
+            } else {
+                iced::widget::button::text
+            })
+            .width(Length::Fill);
+            let remove = Button::new(Text::new("×").size(18))
+                .on_press(Message::RemoveLibrary(i))
+                .style(iced::widget::button::text);
+            Row::new().push(open).push(remove).into()
+        });
+        let add = Button::new(Text::new("+ Add current folder").size(16))
+            .on_press(Message::AddLibrary)
+            .style(iced::widget::button::secondary)
+            .width(Length::Fill);
+        let column = Column::new()
+            .push(header)
+            .push(scrollable(Column::with_children(entries).spacing(2)).height(Length::Fill))
+            .push(add)
+            .spacing(6)
+            .padding(6);
+        Container::new(column)
+            .width(Length::Fixed(220.0))
+            .height(Length::Fill)
+            .into()
+    }
+}
@@ -1,30 +1,36 @@
+use crate::analysis::riff;
 use crate::source::callback::Callback;
+use crate::source::decoded::{Decoded, DecodedSource};
+use crate::source::position::Position;
 
 pub use super::common::*;
 pub use super::waveform::*;
-use async_std::task;
+use super::{Error, Output, Spectrogram};
 use futures::channel::mpsc::unbounded;
 use futures::channel::mpsc::TrySendError;
 use futures::channel::mpsc::UnboundedReceiver;
 use futures::channel::mpsc::UnboundedSender;
 use futures::StreamExt;
+use iced::task;
 use iced::widget::Button;
 use iced::widget::Canvas;
+use iced::widget::Checkbox;
 use iced::widget::Column;
 use iced::widget::Container;
+use iced::widget::PickList;
 use iced::widget::Row;
 use iced::widget::Slider;
 use iced::widget::Space;
 use iced::widget::Svg;
+use iced::widget::Text;
 use iced::Element;
 use iced::Length;
-use rodio::Source;
+use iced::Task;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
 use std::sync;
 use std::thread;
-use std::time::Duration;
 
 // todo: abstract this into a player type
 // ref: https://github.com/tindleaj/miso/blob/master/src/player.rs
@@ -32,43 +38,85 @@ pub struct Player {
     pub waveform: Option<WaveForm>,
     pub controls: Controls,
     pub sender: UnboundedSender<PlayerCommand>,
+    /// The playing file, decoded in the background.
+    pub audio: Option<sync::Arc<Decoded>>,
+    pub file: Option<PathBuf>,
+    pub output: Output,
+    /// Plays to the output. The next one waits for it to let go of the
+    /// output, so a file it writes is finished before it is opened again.
+    thread: Option<thread::JoinHandle<()>>,
+    /// The loop the playing file's `smpl` chunk declares, if any.
+    pub loop_points: Option<(u64, u64)>,
+    /// The file whose waveform is being decoded.
+    loading: Option<PathBuf>,
+    waveform_task: Option<task::Handle>,
 }
 
 pub enum PlayerCommand {
     Play,
     Pause,
     Stop,
-    Seek(f64),
+    /// Sets the sink's volume, as a linear factor.
+    Volume(f32),
+    /// Plays from the given frame.
+    Seek(u64),
+    /// Gets ready to play from the given frame, paused.
+    Cue(u64),
+    /// Sets the frames that playback repeats once it reaches them, or
+    /// plays on to the end. Takes effect from the next `Play`, `Seek` or
+    /// `Cue`.
+    Loop(Option<(u64, u64)>),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum PlayerMsg {
     PlayingStored,
     SinkEmpty,
+    /// Frames played since the start of the file.
+    Position(u64),
+    Error(Error),
 }
 
 pub struct Controls {
     pub is_playing: sync::Arc<sync::atomic::AtomicBool>,
     pub volume: f32,
+    pub loudness_match: bool,
+    /// Repeats the selection, the file's loop points or the whole file.
+    pub looping: bool,
+    pub lanes: Lanes,
+    pub show_spectrogram: bool,
+    pub spectrogram: Spectrogram,
+    /// The header of the playing file, once its waveform starts loading.
+    pub format: Option<Format>,
+    /// Loudness matching gain of the playing file in dB, if it has one.
+    pub gain: Option<f64>,
     pub seekbar: Option<Seekbar>,
 }
 
+/// How often the player thread reports the position, per second of audio.
+const POSITION_UPDATES: u32 = 30;
+
 pub struct Seekbar {
     pub total: u64,
     pub remaining: u64,
     pub seeking: f64,
+    /// Set while the slider is held, so playback does not move it.
+    pub dragging: bool,
 }
 
 impl Seekbar {
     pub fn view(&self) -> Element<'_, Message> {
-        Slider::new(
-            0.0..=1.0,
-            1.0 - (self.remaining as f64 / self.total as f64),
-            Message::Seek,
-        )
-        .step(0.01)
-        .on_release(Message::SeekCommit)
-        .into()
+        let value = if self.dragging {
+            self.seeking
+        } else if self.total == 0 {
+            0.0
+        } else {
+            1.0 - (self.remaining as f64 / self.total as f64)
+        };
+        Slider::new(0.0..=1.0, value, Message::Seek)
+            .step(0.001)
+            .on_release(Message::SeekCommit)
+            .into()
     }
 }
 
@@ -76,7 +124,14 @@ impl Controls {
     pub fn new() -> Self {
         Controls {
             is_playing: sync::Arc::new(false.into()),
-            volume: f32::MAX,
+            volume: 1.0,
+            loudness_match: false,
+            looping: false,
+            lanes: Lanes::default(),
+            show_spectrogram: false,
+            spectrogram: Spectrogram::default(),
+            format: None,
+            gain: None,
             seekbar: None,
         }
     }
@@ -84,7 +139,10 @@ impl Controls {
     pub fn seeking(&mut self, p: f64) {
         match &mut self.seekbar {
             None => (),
-            Some(seekbar) => seekbar.seeking = p,
+            Some(seekbar) => {
+                seekbar.seeking = p;
+                seekbar.dragging = true;
+            }
         }
     }
 
@@ -123,12 +181,66 @@ impl Controls {
         // Slider::new(0.., self.)
     }
 
+    pub fn volume_slider(&self) -> Slider<'_, f32, Message> {
+        Slider::new(0.0..=1.0, self.volume, Message::VolumeChanged)
+            .step(0.01)
+            .on_release(Message::VolumeCommit)
+            .width(Length::Fixed(120.0))
+    }
+
+    pub fn loop_toggle(&self) -> Checkbox<'_, Message> {
+        Checkbox::new("Loop", self.looping)
+            .on_toggle(Message::ToggleLoop)
+            .text_size(14)
+    }
+
+    pub fn lanes_picker(&self) -> PickList<'_, Lanes, [Lanes; 3], Lanes, Message> {
+        PickList::new(Lanes::ALL, Some(self.lanes), Message::LanesSelected).text_size(14)
+    }
+
+    pub fn spectrogram_toggle(&self) -> Checkbox<'_, Message> {
+        Checkbox::new("Spectrogram", self.show_spectrogram)
+            .on_toggle(Message::ToggleSpectrogram)
+            .text_size(14)
+    }
+
+    /// The spectrogram to draw, if it is shown.
+    pub fn spectrogram(&self) -> Option<Spectrogram> {
+        self.show_spectrogram.then_some(self.spectrogram)
+    }
+
+    pub fn loudness_match_toggle(&self) -> Row<'_, Message> {
+        let gain = self
+            .gain
+            .filter(|_| self.loudness_match)
+            .map(|gain| Text::new(format!("{:+.1} dB", gain)).size(14));
+        Row::new()
+            .push(
+                Checkbox::new("Match loudness", self.loudness_match)
+                    .on_toggle(Message::ToggleLoudnessMatch)
+                    .text_size(14),
+            )
+            .push_maybe(gain)
+            .spacing(6)
+            .align_y(iced::Alignment::Center)
+    }
+
     pub fn view(&self) -> Container<Message> {
         let c_row = Row::new()
             .push(self.play_button())
             .push(self.stop_button())
+            .push(self.volume_slider())
+            .push(self.loop_toggle())
+            .push(self.lanes_picker())
+            .push(self.spectrogram_toggle())
+            .push(self.loudness_match_toggle())
+            .push_maybe(
+                self.format
+                    .map(|format| Text::new(format.to_string()).size(14)),
+            )
             .spacing(6)
-            .padding(2);
+            .padding(2)
+            .align_y(iced::Alignment::Center);
         let column = Column::new()
             .push(self.seek_bar())
             .push(c_row)
@@ -149,6 +261,13 @@ impl Player {
             waveform: None,
             controls: Controls::new(),
             sender,
+            audio: None,
+            file: None,
+            output: Output::Default,
+            thread: None,
+            loop_points: None,
+            loading: None,
+            waveform_task: None,
         }
     }
 
@@ -171,30 +290,181 @@ impl Player {
             .center_y(Length::Fill)
     }
 
-    pub fn play_file(&mut self, file_path: PathBuf) -> UnboundedReceiver<PlayerMsg> {
+    /// Starts playing `file_path` with its loudness matching `gain`, or
+    /// leaves the current file alone if it cannot be decoded.
+    pub fn play_file(
+        &mut self,
+        file_path: PathBuf,
+        gain: Option<f64>,
+    ) -> Result<UnboundedReceiver<PlayerMsg>, Error> {
+        let audio = Decoded::spawn(load_source(&file_path)?);
+        // before playing, so the first samples are already at the matched level
+        self.controls.gain = gain;
         self.controls
             .is_playing
             .store(true, sync::atomic::Ordering::SeqCst);
-        let audio_buffer: WaveForm = load_source(&file_path).into();
-        let samples_len = audio_buffer.samples.len();
+        // without a length in the header, the seekbar grows with the decoder
+        let samples_len = audio.expected_frames.unwrap_or(0) as u64;
         self.controls.seekbar = Some(Seekbar {
-            total: samples_len as u64,
-            remaining: samples_len as u64,
+            total: samples_len,
+            remaining: samples_len,
             seeking: 0.0,
+            dragging: false,
         });
-        let audio_length_s: f64 = samples_len as f64 / audio_buffer.sample_rate as f64;
-        self.waveform = Some(audio_buffer);
+        self.waveform = None;
+        self.controls.format = None;
+        self.audio = Some(sync::Arc::clone(&audio));
+        self.loop_points = riff::loop_points(&file_path);
+        self.file = Some(file_path);
+        let receiver = self.spawn_thread(audio);
+        self.apply_volume();
+        self.send_loop();
+        self.play();
+        Ok(receiver)
+    }
+
+    /// Switches to `output`. Returns the new player thread's messages if
+    /// a file is loaded, which carries on from where it was.
+    pub fn set_output(&mut self, output: Output) -> Option<UnboundedReceiver<PlayerMsg>> {
+        self.output = output;
+        let audio = sync::Arc::clone(self.audio.as_ref()?);
+        let receiver = self.spawn_thread(audio);
+        self.apply_volume();
+        self.send_loop();
+        self.resume();
+        Some(receiver)
+    }
+
+    /// Starts playing again from the current position, or gets ready to if
+    /// paused, so changes to the loop take effect.
+    fn resume(&self) {
+        let command = if self
+            .controls
+            .is_playing
+            .load(sync::atomic::Ordering::SeqCst)
+        {
+            PlayerCommand::Seek(self.position())
+        } else {
+            PlayerCommand::Cue(self.position())
+        };
+        handle_player_command_err(self.sender.unbounded_send(command));
+    }
+
+    /// Frames into the file the seekbar is at.
+    fn position(&self) -> u64 {
+        self.controls
+            .seekbar
+            .as_ref()
+            .map_or(0, |s| s.total - s.remaining)
+    }
+
+    /// The frames playback repeats: the selection, else the file's loop
+    /// points, else all of it.
+    fn loop_bounds(&self) -> Option<(u64, u64)> {
+        if !self.controls.looping {
+            return None;
+        }
+        let len = match &self.waveform {
+            Some(waveform) if self.loading.is_none() => Some(waveform.len() as u64),
+            _ => self.audio.as_ref()?.expected_frames.map(|n| n as u64),
+        }
+        // an unknown length loops wherever the file turns out to end
+        .unwrap_or(u64::MAX)
+        .max(1);
+        let (start, end) = self
+            .waveform
+            .as_ref()
+            .and_then(|waveform| waveform.selection)
+            .or(self.loop_points)
+            .unwrap_or((0, len));
+        Some((start.min(len - 1), end.min(len)))
+    }
+
+    fn send_loop(&self) {
+        let region = self.loop_bounds();
+        handle_player_command_err(self.sender.unbounded_send(PlayerCommand::Loop(region)))
+    }
+
+    /// Turns looping on or off without interrupting playback for longer
+    /// than it takes to refill the output.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.controls.looping = looping;
+        if self.audio.is_some() {
+            self.send_loop();
+            self.resume();
+        }
+    }
+
+    /// Shows or hides the spectrogram, or changes how it is drawn.
+    pub fn set_spectrogram(&mut self, show: bool, spectrogram: Spectrogram) {
+        self.controls.show_spectrogram = show;
+        self.controls.spectrogram = spectrogram;
+        if let Some(waveform) = &mut self.waveform {
+            waveform.set_spectrogram(self.controls.spectrogram());
+        }
+    }
+
+    /// Starts a thread playing `audio` to the output, replacing (and so
+    /// ending) the previous one.
+    fn spawn_thread(&mut self, audio: sync::Arc<Decoded>) -> UnboundedReceiver<PlayerMsg> {
         let is_playing = sync::Arc::clone(&self.controls.is_playing);
+        let output = self.output.clone();
         let (sender, mut receiver) = unbounded();
         self.sender = sender;
         let (player_sender, player_receiver) = unbounded();
-        thread::spawn(move || {
-            let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
-            let sink = rodio::Sink::try_new(&stream_handle).unwrap();
+        // ends once it sees its commands are gone
+        let previous = self.thread.take();
+        self.thread = Some(thread::spawn(move || {
+            if let Some(previous) = previous {
+                let _ = previous.join();
+            }
             let send_msg = Box::new(move |msg| {
                 player_sender.unbounded_send(msg).unwrap_or(());
             });
-            task::block_on(async move {
+            let (stream, sink) = match output.open() {
+                Ok(output) => output,
+                Err(e) => {
+                    is_playing.store(false, sync::atomic::Ordering::SeqCst);
+                    send_msg(PlayerMsg::Error(Error::Output(e)));
+                    return;
+                }
+            };
+            async_std::task::block_on(async move {
+                let interval = (audio.sample_rate / POSITION_UPDATES) as u64;
+                let report = || -> Box<dyn Send + Fn(u64)> {
+                    let send_msg = send_msg.clone();
+                    Box::new(move |frames| send_msg(PlayerMsg::Position(frames)))
+                };
+                let play_from = |frame: u64, region: Option<(u64, u64)>| {
+                    stream.clear(&sink);
+                    // past the loop, start at its beginning
+                    let frame = match region {
+                        Some((start, end)) if frame >= end => start,
+                        _ => frame,
+                    };
+                    let source = DecodedSource::new(sync::Arc::clone(&audio), frame);
+                    match region {
+                        Some((start, end)) => {
+                            sink.append(
+                                Position::new(
+                                    source.looping(start, end),
+                                    frame,
+                                    interval,
+                                    report(),
+                                )
+                                .looping(start, end),
+                            );
+                        }
+                        None => {
+                            sink.append(Position::new(source, frame, interval, report()));
+                            sink.append::<Callback<PlayerMsg, f32>>(Callback::new(
+                                send_msg.clone(),
+                                PlayerMsg::SinkEmpty,
+                            ));
+                        }
+                    }
+                };
+                let mut region = None;
                 loop {
                     if let Some(msg) = receiver.next().await {
                         match msg {
@@ -202,11 +472,7 @@ impl Player {
                                 is_playing.store(true, sync::atomic::Ordering::SeqCst);
                                 send_msg(PlayerMsg::PlayingStored);
                                 if sink.empty() {
-                                    sink.append(load_source(&file_path));
-                                    sink.append::<Callback<PlayerMsg, f32>>(Callback::new(
-                                        send_msg.clone(),
-                                        PlayerMsg::SinkEmpty,
-                                    ));
+                                    play_from(0, region);
                                 }
                                 sink.play();
                             }
@@ -218,31 +484,28 @@ impl Player {
                             PlayerCommand::Stop => {
                                 is_playing.store(false, sync::atomic::Ordering::SeqCst);
                                 send_msg(PlayerMsg::PlayingStored);
-                                sink.clear();
+                                stream.clear(&sink);
                             }
-                            PlayerCommand::Seek(p) => {
-                                sink.clear();
+                            PlayerCommand::Volume(volume) => sink.set_volume(volume),
+                            PlayerCommand::Cue(frame) => {
+                                is_playing.store(false, sync::atomic::Ordering::SeqCst);
+                                send_msg(PlayerMsg::PlayingStored);
+                                play_from(frame, region);
+                            }
+                            PlayerCommand::Seek(frame) => {
                                 is_playing.store(true, sync::atomic::Ordering::SeqCst);
                                 send_msg(PlayerMsg::PlayingStored);
-                                let secs_to_skip = audio_length_s * p;
-                                sink.append(
-                                    load_source(&file_path)
-                                        .skip_duration(Duration::from_secs_f64(secs_to_skip)),
-                                );
-                                sink.append::<Callback<PlayerMsg, f32>>(Callback::new(
-                                    send_msg.clone(),
-                                    PlayerMsg::SinkEmpty,
-                                ));
+                                play_from(frame, region);
                                 sink.play();
                             }
+                            PlayerCommand::Loop(bounds) => region = bounds,
                         }
                     } else {
                         break;
                     }
                 }
             });
-        });
-        self.play();
+        }));
         player_receiver
     }
 
@@ -254,12 +517,146 @@ impl Player {
         handle_player_command_err(self.sender.unbounded_send(PlayerCommand::Pause))
     }
 
+    pub fn set_volume(&mut self, volume: f32) {
+        self.controls.volume = volume;
+        self.apply_volume();
+    }
+
+    /// Sets the loudness matching gain of the playing file, in dB.
+    pub fn set_gain(&mut self, gain: Option<f64>) {
+        self.controls.gain = gain;
+        self.apply_volume();
+    }
+
+    /// Sends the volume, times the loudness matching gain if it is on.
+    pub fn apply_volume(&self) {
+        let gain = match self.controls.gain {
+            Some(gain) if self.controls.loudness_match => 10f64.powf(gain / 20.0) as f32,
+            _ => 1.0,
+        };
+        let volume = self.controls.volume * gain;
+        handle_player_command_err(self.sender.unbounded_send(PlayerCommand::Volume(volume)))
+    }
+
     pub fn stop(&mut self) {
+        self.set_position(0);
         handle_player_command_err(self.sender.unbounded_send(PlayerCommand::Stop))
     }
 
+    /// Seeks to `p` (0 to 1) of the way through the file.
     pub fn seek(&mut self, p: f64) {
-        handle_player_command_err(self.sender.unbounded_send(PlayerCommand::Seek(p)))
+        let frames = match &mut self.controls.seekbar {
+            Some(seekbar) => {
+                seekbar.dragging = false;
+                (seekbar.total as f64 * p.clamp(0.0, 1.0)) as u64
+            }
+            None => return,
+        };
+        self.seek_to(frames);
+    }
+
+    /// Plays from `seconds` after (or before) the current position.
+    pub fn seek_by(&mut self, seconds: f64) {
+        let (rate, total) = match (&self.audio, &self.controls.seekbar) {
+            (Some(audio), Some(seekbar)) => (audio.sample_rate as f64, seekbar.total),
+            _ => return,
+        };
+        let frames = self.position() as f64 + seconds * rate;
+        self.seek_to(frames.clamp(0.0, total.saturating_sub(1) as f64) as u64);
+    }
+
+    /// Plays from `frames` into the file, dropping the selection unless
+    /// `frames` is inside it.
+    pub fn seek_to(&mut self, frames: u64) {
+        self.set_position(frames);
+        if let Some(waveform) = &mut self.waveform
+            && !waveform
+                .selection
+                .is_some_and(|(start, end)| (start..end).contains(&frames))
+        {
+            waveform.selection = None;
+        }
+        self.send_loop();
+        handle_player_command_err(self.sender.unbounded_send(PlayerCommand::Seek(frames)))
+    }
+
+    /// Selects the frames from `start` to `end` and plays them in a loop.
+    pub fn loop_region(&mut self, start: u64, end: u64) {
+        self.set_position(start);
+        if let Some(waveform) = &mut self.waveform {
+            waveform.selection = Some((start, end.max(start + 1)));
+        }
+        self.controls.looping = true;
+        self.send_loop();
+        handle_player_command_err(self.sender.unbounded_send(PlayerCommand::Seek(start)))
+    }
+
+    /// Moves the seekbar and the playhead to `frames` into the file.
+    pub fn set_position(&mut self, frames: u64) {
+        if let Some(seekbar) = &mut self.controls.seekbar {
+            if let Some(audio) = &self.audio
+                && audio.expected_frames.is_none()
+            {
+                seekbar.total = audio.frames() as u64;
+            }
+            seekbar.remaining = seekbar.total.saturating_sub(frames);
+        }
+        if let Some(waveform) = &mut self.waveform {
+            waveform.playhead = frames;
+        }
+    }
+
+    /// Builds the waveform of the playing file, `file_path`, in the
+    /// background as it is decoded, replacing (and cancelling) any earlier
+    /// one.
+    pub fn load_waveform(&mut self, file_path: PathBuf) -> Task<Message> {
+        let audio = match &self.audio {
+            Some(audio) => sync::Arc::clone(audio),
+            None => return Task::none(),
+        };
+        self.loading = Some(file_path.clone());
+        let (task, handle) =
+            Task::run(WaveForm::load(file_path, audio), Message::WaveFormChunk).abortable();
+        self.waveform_task = Some(handle.abort_on_drop());
+        task
+    }
+
+    pub fn add_waveform_chunk(&mut self, chunk: WaveFormChunk) {
+        if self.loading.as_ref() != Some(&chunk.path) {
+            return;
+        }
+        let playhead = self.position();
+        self.controls.format = Some(chunk.format);
+        let lanes = self.controls.lanes;
+        let spectrogram = self.controls.spectrogram();
+        let waveform = self.waveform.get_or_insert_with(|| {
+            let mut waveform = WaveForm::new(chunk.expected_len, chunk.format, lanes);
+            waveform.set_spectrogram(spectrogram);
+            waveform.playhead = playhead;
+            waveform
+        });
+        if let Some(peaks) = chunk.peaks {
+            waveform.set_peaks(sync::Arc::unwrap_or_clone(peaks));
+        }
+        waveform.extend(&chunk.samples);
+        if chunk.done {
+            // the header's length is only an estimate, if it is there at all
+            let len = waveform.decoded_len();
+            waveform.expected_len = Some(len);
+            if let Some(seekbar) = &mut self.controls.seekbar {
+                let played = seekbar.total - seekbar.remaining;
+                seekbar.total = len.max(1) as u64;
+                seekbar.remaining = seekbar.total.saturating_sub(played);
+            }
+            self.loading = None;
+            self.waveform_task = None;
+            let estimate = self.audio.as_ref().and_then(|audio| audio.expected_frames);
+            if self.controls.looping && estimate != Some(len) {
+                // the loop was cut to the estimate
+                self.send_loop();
+                self.resume();
+            }
+        }
     }
 }
 
@@ -271,7 +668,10 @@ fn handle_player_command_err<T>(res: Result<(), TrySendError<T>>) {
 
 pub fn load_source<T: std::convert::AsRef<std::path::Path>>(
     file_path: T,
-) -> rodio::Decoder<BufReader<File>> {
-    let file = File::open(file_path).unwrap();
-    rodio::Decoder::new(BufReader::new(file)).unwrap()
+) -> Result<rodio::Decoder<BufReader<File>>, Error> {
+    let file_path = file_path.as_ref();
+    let file =
+        File::open(file_path).map_err(|e| Error::Open(file_path.to_owned(), e.to_string()))?;
+    rodio::Decoder::new(BufReader::new(file))
+        .map_err(|e| Error::Decode(file_path.to_owned(), e.to_string()))
 }
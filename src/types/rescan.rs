@@ -0,0 +1,193 @@
+use super::{DirCache, FileList, FileRecord, IndexUpdate};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RescanReport {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+}
+
+impl fmt::Display for RescanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Library rescanned: {} added, {} removed, {} modified",
+            self.added, self.removed, self.modified
+        )
+    }
+}
+
+/// What the index knows about one root, keyed for the diff.
+struct Stored {
+    records: HashMap<PathBuf, FileRecord>,
+    children: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Stored {
+    fn load(dir_cache: &DirCache, root: &Path) -> rusqlite::Result<Stored> {
+        let mut records = dir_cache.entries_under(root)?;
+        records.extend(dir_cache.get(root)?);
+        Ok(Stored::new(records))
+    }
+
+    fn new(records: Vec<FileRecord>) -> Stored {
+        let records: HashMap<PathBuf, FileRecord> =
+            records.into_iter().map(|r| (r.path.clone(), r)).collect();
+        let mut children: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for path in records.keys() {
+            if let Some(parent) = path.parent() {
+                children
+                    .entry(parent.to_owned())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+        Stored { records, children }
+    }
+
+    fn children(&self, dir: &Path) -> &[PathBuf] {
+        self.children.get(dir).map_or(&[], Vec::as_slice)
+    }
+
+    fn count_files(&self, path: &Path) -> usize {
+        match self.records.get(path) {
+            Some(r) if r.is_dir => self
+                .children(path)
+                .iter()
+                .map(|c| self.count_files(c))
+                .sum(),
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+}
+
+/// Diffs the filesystem against the index. Directories whose mtime still
+/// matches are not listed again (their entries cannot have changed), and
+/// files are only re-probed when their size or mtime differ.
+#[derive(Default)]
+pub struct Rescan {
+    pub update: IndexUpdate,
+    pub report: RescanReport,
+}
+
+impl Rescan {
+    pub fn run(dir_cache: &DirCache) -> rusqlite::Result<Rescan> {
+        let mut rescan = Rescan::default();
+        for root in dir_cache.roots() {
+            let stored = Stored::load(dir_cache, root)?;
+            rescan.dir(root, &stored);
+        }
+        Ok(rescan)
+    }
+
+    fn dir(&mut self, dir: &Path, stored: &Stored) {
+        let current = match FileRecord::stat(dir) {
+            Some(current) if current.is_dir => current,
+            // replaced by a file, which has no listing to diff
+            Some(_) => {
+                self.removed(dir, stored);
+                return self.added(dir);
+            }
+            None => return self.removed(dir, stored),
+        };
+        let listing_unchanged = stored
+            .records
+            .get(dir)
+            .is_some_and(|r| r.is_dir && r.mtime == current.mtime);
+        if listing_unchanged {
+            for child in stored.children(dir) {
+                self.entry(child, stored);
+            }
+            return;
+        }
+        self.update.upserted.push(current);
+        let on_disk: HashSet<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| FileList::file_filter(p.to_owned()))
+                .collect(),
+            Err(e) => {
+                eprintln!("rescan: could not list {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        for child in stored.children(dir) {
+            if !on_disk.contains(child) {
+                self.removed(child, stored);
+            }
+        }
+        for child in &on_disk {
+            self.entry(child, stored);
+        }
+    }
+
+    fn entry(&mut self, path: &Path, stored: &Stored) {
+        match stored.records.get(path) {
+            None => self.added(path),
+            Some(r) if r.is_dir => self.dir(path, stored),
+            Some(r) => match FileRecord::stat(path) {
+                None => self.removed(path, stored),
+                Some(current) if current.is_dir => {
+                    self.removed(path, stored);
+                    self.added(path);
+                }
+                Some(current) if current.size == r.size && current.mtime == r.mtime => (),
+                Some(mut current) => {
+                    current.probe();
+                    self.update.upserted.push(current);
+                    self.report.modified += 1;
+                }
+            },
+        }
+    }
+
+    fn added(&mut self, path: &Path) {
+        let records = FileRecord::walk(path);
+        self.report.added += records.iter().filter(|r| !r.is_dir).count();
+        self.update.upserted.extend(records);
+    }
+
+    fn removed(&mut self, path: &Path, stored: &Stored) {
+        self.report.removed += stored.count_files(path);
+        self.update.removed.push(path.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_directories_that_became_files() {
+        let root = std::env::temp_dir().join(format!("tundra-rescan-{}", std::process::id()));
+        let dir = root.join("loop.wav");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.wav"), []).unwrap();
+        fs::write(dir.join("b.wav"), []).unwrap();
+        let stored = Stored::new(FileRecord::walk(&root));
+        fs::remove_dir_all(&dir).unwrap();
+        fs::write(&dir, []).unwrap();
+        let mut rescan = Rescan::default();
+        rescan.dir(&root, &stored);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(rescan.update.removed, std::slice::from_ref(&dir));
+        assert!(
+            rescan
+                .update
+                .upserted
+                .iter()
+                .any(|r| r.path == dir && !r.is_dir)
+        );
+        let report = RescanReport {
+            added: 1,
+            removed: 2,
+            modified: 0,
+        };
+        assert_eq!(rescan.report, report);
+    }
+}
@@ -1,5 +1,891 @@
+use super::{FileRecord, Library};
+use crate::analysis::key::Key;
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use std::cell::OnceCell;
+use std::fmt;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::CharIndices;
+
+/// A parsed search query.
+///
+/// Bare words are fuzzy matched against the path relative to the searched
+/// directory, `"quoted phrases"` must appear verbatim (case-insensitive),
+/// and `key:value` terms filter on file metadata. Terms are joined with an
+/// implicit `AND`; `OR`, `NOT`/`-` and parentheses work as usual.
+///
+/// `key:Am` matches A minor exactly, `key:~Am` anything that mixes with it
+/// (relative major and Camelot neighbours).
+#[derive(Debug, Clone, PartialEq)]
 pub enum SearchDSL {
     Literal(String),
-    Metadata(String, String),
+    Phrase(String),
+    Metadata(Field, Constraint),
     Negate(Box<SearchDSL>),
+    And(Vec<SearchDSL>),
+    Or(Vec<SearchDSL>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Ext,
+    Name,
+    Dir,
+    Duration,
+    SampleRate,
+    Channels,
+    BitDepth,
+    Format,
+    Size,
+    Peak,
+    Rms,
+    Loudness,
+    DcOffset,
+    Bpm,
+    Key,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    Text(String),
+    Eq(f64),
+    Lt(f64),
+    Le(f64),
+    Gt(f64),
+    Ge(f64),
+    Range(f64, f64),
+    Key(Key),
+    CompatibleKey(Key),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Characters into the query.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.position + 1, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error<T>(position: usize, message: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError {
+        position,
+        message: message.into(),
+    })
+}
+
+impl Field {
+    pub fn from_key(key: &str) -> Option<Field> {
+        match key.to_lowercase().as_str() {
+            "ext" | "format" | "type" => Some(Field::Ext),
+            "name" | "file" => Some(Field::Name),
+            "dir" | "path" | "in" => Some(Field::Dir),
+            "dur" | "duration" | "len" | "length" => Some(Field::Duration),
+            "sr" | "rate" | "samplerate" => Some(Field::SampleRate),
+            "ch" | "channels" => Some(Field::Channels),
+            "bits" | "depth" | "bitdepth" => Some(Field::BitDepth),
+            "codec" | "fmt" => Some(Field::Format),
+            "size" => Some(Field::Size),
+            "peak" | "tp" | "truepeak" => Some(Field::Peak),
+            "rms" => Some(Field::Rms),
+            "lufs" | "loudness" => Some(Field::Loudness),
+            "dc" | "dcoffset" => Some(Field::DcOffset),
+            "bpm" | "tempo" => Some(Field::Bpm),
+            "key" => Some(Field::Key),
+            _ => None,
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        !matches!(
+            self,
+            Field::Ext | Field::Name | Field::Dir | Field::Format | Field::Key
+        )
+    }
+
+    /// Parses a single number for this field, accepting the units that make
+    /// sense for it (`500ms`, `2s`, `1m`, `44.1k`, `48khz`, `stereo`, `-1dbtp`,
+    /// `0.5%`, ...).
+    fn parse_number(self, s: &str) -> Option<f64> {
+        let s = s.trim().to_lowercase();
+        let number = |n: &str, scale: f64| n.parse::<f64>().ok().map(|x| x * scale);
+        match self {
+            Field::Duration => {
+                if let Some(n) = s.strip_suffix("ms") {
+                    number(n, 0.001)
+                } else if let Some(n) = s.strip_suffix("min") {
+                    number(n, 60.0)
+                } else if let Some(n) = s.strip_suffix('m') {
+                    number(n, 60.0)
+                } else if let Some(n) = s.strip_suffix('s') {
+                    number(n, 1.0)
+                } else {
+                    number(&s, 1.0)
+                }
+            }
+            Field::SampleRate => {
+                if let Some(n) = s.strip_suffix("khz").or_else(|| s.strip_suffix('k')) {
+                    number(n, 1000.0)
+                } else {
+                    number(s.strip_suffix("hz").unwrap_or(&s), 1.0)
+                }
+            }
+            Field::Channels => match s.as_str() {
+                "mono" => Some(1.0),
+                "stereo" => Some(2.0),
+                _ => number(&s, 1.0),
+            },
+            Field::BitDepth => number(s.strip_suffix("bit").unwrap_or(&s), 1.0),
+            Field::Size => {
+                let s = s.strip_suffix('b').unwrap_or(&s);
+                if let Some(n) = s.strip_suffix('k') {
+                    number(n, 1024.0)
+                } else if let Some(n) = s.strip_suffix('m') {
+                    number(n, 1024.0 * 1024.0)
+                } else if let Some(n) = s.strip_suffix('g') {
+                    number(n, 1024.0 * 1024.0 * 1024.0)
+                } else {
+                    number(s, 1.0)
+                }
+            }
+            Field::Peak | Field::Rms | Field::Loudness => {
+                let n = ["dbtp", "dbfs", "db", "lufs", "lu"]
+                    .iter()
+                    .find_map(|unit| s.strip_suffix(unit))
+                    .unwrap_or(&s);
+                number(n, 1.0)
+            }
+            Field::Bpm => number(s.strip_suffix("bpm").unwrap_or(&s), 1.0),
+            Field::DcOffset => match s.strip_suffix('%') {
+                Some(n) => number(n, 0.01),
+                None => number(&s, 1.0),
+            },
+            Field::Ext | Field::Name | Field::Dir | Field::Format | Field::Key => None,
+        }
+    }
+
+    /// How far off a value may be and still count for `field:value`.
+    fn tolerance(self) -> f64 {
+        match self {
+            Field::Duration | Field::Peak | Field::Rms | Field::Loudness | Field::Bpm => 0.5,
+            _ => 0.0,
+        }
+    }
+
+    fn parse_constraint(self, value: &str, position: usize) -> Result<Constraint, ParseError> {
+        if value.is_empty() {
+            return error(position, "expected a value after `:`");
+        }
+        if self == Field::Key {
+            let (compatible, name) = match value.strip_prefix('~') {
+                Some(name) => (true, name),
+                None => (false, value),
+            };
+            return match Key::parse(name) {
+                Some(key) if compatible => Ok(Constraint::CompatibleKey(key)),
+                Some(key) => Ok(Constraint::Key(key)),
+                None => error(position, format!("`{}` is not a key", name)),
+            };
+        }
+        if !self.is_numeric() {
+            let value = value.to_lowercase();
+            return Ok(Constraint::Text(match self {
+                Field::Ext => value.trim_start_matches('.').to_owned(),
+                _ => value,
+            }));
+        }
+        let number = |s: &str| {
+            self.parse_number(s).map_or_else(
+                || {
+                    error(
+                        position,
+                        format!("`{}` is not a valid value for `{}`", s, self),
+                    )
+                },
+                Ok,
+            )
+        };
+        let (op, rest) = ["<=", ">=", "<", ">", "="]
+            .iter()
+            .find_map(|op| value.strip_prefix(op).map(|rest| (*op, rest)))
+            .unwrap_or(("", value));
+        match op {
+            "<=" => Ok(Constraint::Le(number(rest)?)),
+            ">=" => Ok(Constraint::Ge(number(rest)?)),
+            "<" => Ok(Constraint::Lt(number(rest)?)),
+            ">" => Ok(Constraint::Gt(number(rest)?)),
+            "=" => Ok(Constraint::Eq(number(rest)?)),
+            _ => match range_split(rest) {
+                Some((lo, hi)) => {
+                    let (lo, hi) = (number(lo)?, number(hi)?);
+                    Ok(Constraint::Range(lo.min(hi), lo.max(hi)))
+                }
+                None => Ok(Constraint::Eq(number(rest)?)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Field::Ext => "ext",
+            Field::Name => "name",
+            Field::Dir => "dir",
+            Field::Duration => "dur",
+            Field::SampleRate => "sr",
+            Field::Channels => "ch",
+            Field::BitDepth => "bits",
+            Field::Format => "codec",
+            Field::Size => "size",
+            Field::Peak => "peak",
+            Field::Rms => "rms",
+            Field::Loudness => "lufs",
+            Field::DcOffset => "dc",
+            Field::Bpm => "bpm",
+            Field::Key => "key",
+        })
+    }
+}
+
+/// Splits `lo-hi` on the first dash that is not a sign, so `-20--10` works.
+fn range_split(s: &str) -> Option<(&str, &str)> {
+    let bytes = s.as_bytes();
+    (1..bytes.len())
+        .find(|&i| bytes[i] == b'-' && bytes[i - 1] != b'-')
+        .map(|i| (&s[..i], &s[i + 1..]))
+}
+
+impl Constraint {
+    fn accepts(&self, x: f64, tolerance: f64) -> bool {
+        match *self {
+            Constraint::Eq(y) => (x - y).abs() <= tolerance,
+            Constraint::Lt(y) => x < y,
+            Constraint::Le(y) => x <= y,
+            Constraint::Gt(y) => x > y,
+            Constraint::Ge(y) => x >= y,
+            Constraint::Range(lo, hi) => lo <= x && x <= hi,
+            Constraint::Text(_) | Constraint::Key(_) | Constraint::CompatibleKey(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+    Term(Field, Constraint),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push((i, Token::Open));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((i, Token::Close));
+            }
+            '"' => {
+                chars.next();
+                tokens.push((i, Token::Phrase(quoted(&mut chars, i)?)));
+            }
+            '-' | '!' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, n)) if !n.is_whitespace() && n != ')' => tokens.push((i, Token::Not)),
+                    _ => return error(i, format!("`{}` must be followed by a term", c)),
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                let mut value = None;
+                while let Some(&(j, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    if c == ':' && value.is_none() {
+                        let value = value.insert(String::new());
+                        if let Some(&(_, '"')) = chars.peek() {
+                            chars.next();
+                            value.push_str(&quoted(&mut chars, j)?);
+                            break;
+                        }
+                    } else if let Some(value) = value.as_mut() {
+                        value.push(c);
+                    } else {
+                        word.push(c);
+                    }
+                }
+                let token = match (word.as_str(), value) {
+                    ("AND", None) => Token::And,
+                    ("OR", None) | ("|", None) => Token::Or,
+                    ("NOT", None) => Token::Not,
+                    (_, None) => Token::Word(word),
+                    (key, Some(value)) => match Field::from_key(key) {
+                        Some(field) => Token::Term(field, field.parse_constraint(&value, i)?),
+                        None => return error(i, format!("unknown field `{}`", key)),
+                    },
+                };
+                tokens.push((i, token));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn quoted(chars: &mut Peekable<CharIndices>, start: usize) -> Result<String, ParseError> {
+    let mut s = String::new();
+    for (_, c) in chars.by_ref() {
+        if c == '"' {
+            return Ok(s);
+        }
+        s.push(c);
+    }
+    error(start, "unterminated quote")
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(i, _)| *i)
+    }
+
+    fn or(&mut self) -> Result<SearchDSL, ParseError> {
+        let mut terms = vec![self.and()?];
+        while let Some(Token::Or) = self.peek() {
+            self.pos += 1;
+            terms.push(self.and()?);
+        }
+        Ok(flatten(terms, SearchDSL::Or))
+    }
+
+    fn and(&mut self) -> Result<SearchDSL, ParseError> {
+        let mut terms = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                None | Some(Token::Or) | Some(Token::Close) => break,
+                Some(Token::And) => self.pos += 1,
+                Some(_) => (),
+            }
+            terms.push(self.unary()?);
+        }
+        // indexed metadata is cheap to compare, so fuzzy matching goes last
+        terms.sort_by_key(SearchDSL::cost);
+        Ok(flatten(terms, SearchDSL::And))
+    }
+
+    fn unary(&mut self) -> Result<SearchDSL, ParseError> {
+        let position = self.position();
+        let token = match self.tokens.get(self.pos) {
+            Some((_, t)) => t.clone(),
+            None => return error(position, "expected a search term"),
+        };
+        self.pos += 1;
+        match token {
+            Token::Not => Ok(SearchDSL::Negate(Box::new(self.unary()?))),
+            Token::Word(w) => Ok(SearchDSL::Literal(w)),
+            Token::Phrase(p) => Ok(SearchDSL::Phrase(p.to_lowercase())),
+            Token::Term(field, constraint) => Ok(SearchDSL::Metadata(field, constraint)),
+            Token::Open => {
+                let inner = self.or()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    _ => error(position, "unclosed `(`"),
+                }
+            }
+            Token::Close => error(position, "unexpected `)`"),
+            Token::And => error(position, "`AND` needs a term on both sides"),
+            Token::Or => error(position, "`OR` needs a term on both sides"),
+        }
+    }
+}
+
+fn flatten(mut terms: Vec<SearchDSL>, f: fn(Vec<SearchDSL>) -> SearchDSL) -> SearchDSL {
+    if terms.len() == 1 {
+        terms.remove(0)
+    } else {
+        f(terms)
+    }
+}
+
+impl SearchDSL {
+    pub fn parse(input: &str) -> Result<SearchDSL, ParseError> {
+        let parse = || {
+            let mut parser = Parser {
+                tokens: tokenize(input)?,
+                pos: 0,
+                end: input.len(),
+            };
+            let query = parser.or()?;
+            match parser.peek() {
+                None => Ok(query),
+                Some(_) => error(parser.position(), "unexpected `)`"),
+            }
+        };
+        // the tokenizer counts bytes
+        parse().map_err(|e| ParseError {
+            position: input[..e.position].chars().count(),
+            message: e.message,
+        })
+    }
+
+    fn cost(&self) -> u8 {
+        match self {
+            SearchDSL::Metadata(_, _) => 0,
+            SearchDSL::Phrase(_) => 1,
+            SearchDSL::Literal(_) => 2,
+            SearchDSL::Negate(inner) => inner.cost(),
+            SearchDSL::And(terms) | SearchDSL::Or(terms) => {
+                terms.iter().map(SearchDSL::cost).max().unwrap_or(0)
+            }
+        }
+    }
+
+    pub fn matches(&self, candidate: &Candidate, matcher: &SkimMatcherV2) -> Option<Match> {
+        self.eval(candidate, matcher, false)
+    }
+
+    // Negated words use plain substring matching: fuzzy matching `-loop`
+    // would also throw away e.g. `long_open_pad`.
+    fn eval(&self, candidate: &Candidate, matcher: &SkimMatcherV2, negated: bool) -> Option<Match> {
+        match self {
+            SearchDSL::Literal(word) if negated => candidate
+                .relative_lower()
+                .contains(&word.to_lowercase())
+                .then(Match::default),
+            SearchDSL::Literal(word) => candidate.fuzzy(word, matcher),
+            SearchDSL::Phrase(phrase) => candidate.phrase(phrase),
+            SearchDSL::Metadata(field, constraint) => {
+                candidate.check(*field, constraint).then(Match::default)
+            }
+            SearchDSL::Negate(inner) => match inner.eval(candidate, matcher, !negated) {
+                Some(_) => None,
+                None => Some(Match::default()),
+            },
+            SearchDSL::And(terms) => terms.iter().try_fold(Match::default(), |acc, t| {
+                t.eval(candidate, matcher, negated).map(|m| acc.and(m))
+            }),
+            SearchDSL::Or(terms) => terms
+                .iter()
+                .filter_map(|t| t.eval(candidate, matcher, negated))
+                .max_by_key(Match::rank),
+        }
+    }
+
+    /// Runs the query over groups of records, each shown relative to its
+    /// base directory, best matches first.
+    pub fn search<'a, I>(&self, groups: I) -> Vec<Hit>
+    where
+        I: IntoIterator<Item = (&'a Path, &'a [FileRecord])>,
+    {
+        let matcher = SkimMatcherV2::default();
+        let mut hits: Vec<Hit> = groups
+            .into_iter()
+            .flat_map(|(base, records)| {
+                let matcher = &matcher;
+                records.iter().filter_map(move |record| {
+                    self.matches(&Candidate::new(base, record), matcher)
+                        .map(|m| Hit {
+                            path: record.path.to_owned(),
+                            base: base.to_owned(),
+                            record: record.clone(),
+                            m,
+                        })
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.m.rank()
+                .cmp(&a.m.rank())
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        hits
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub path: PathBuf,
+    /// The directory the match indices are relative to.
+    pub base: PathBuf,
+    pub record: FileRecord,
+    pub m: Match,
+}
+
+/// Where a search looks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchScope {
+    CurrentDir,
+    Library(Library),
+    AllLibraries,
+}
+
+impl fmt::Display for SearchScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchScope::CurrentDir => f.write_str("This folder"),
+            SearchScope::Library(library) => f.write_str(&library.name),
+            SearchScope::AllLibraries => f.write_str("All libraries"),
+        }
+    }
+}
+
+impl SearchScope {
+    pub fn options(libraries: &[Library]) -> Vec<SearchScope> {
+        let mut options = vec![SearchScope::CurrentDir];
+        options.extend(libraries.iter().cloned().map(SearchScope::Library));
+        if !libraries.is_empty() {
+            options.push(SearchScope::AllLibraries);
+        }
+        options
+    }
+
+    /// The `(base, root)` pairs to search: hits are found below `root` and
+    /// labelled relative to `base`.
+    pub fn roots(&self, current_dir: &Path, libraries: &[Library]) -> Vec<(PathBuf, PathBuf)> {
+        match self {
+            SearchScope::CurrentDir => vec![(current_dir.to_owned(), current_dir.to_owned())],
+            SearchScope::Library(library) => vec![(library.path.clone(), library.path.clone())],
+            // keep the root's own folder name so hits from different libraries can be told apart
+            SearchScope::AllLibraries => {
+                let mut roots: Vec<(PathBuf, PathBuf)> = Vec::new();
+                // a library inside another is searched with it, not twice
+                for l in libraries.iter().map(|l| &l.path) {
+                    if libraries
+                        .iter()
+                        .any(|o| l.starts_with(&o.path) && o.path != *l)
+                        || roots.iter().any(|(_, root)| root == l)
+                    {
+                        continue;
+                    }
+                    // a library at the filesystem root has no folder name to keep
+                    let base = l.parent().unwrap_or(l).to_owned();
+                    roots.push((base, l.clone()));
+                }
+                roots
+            }
+        }
+    }
+}
+
+/// How well a path matched a query. Hits in the file name rank above hits
+/// that only matched somewhere in the directory part of the path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Match {
+    pub name_hits: u32,
+    pub score: i64,
+    /// Char indices into the path relative to the searched directory.
+    pub indices: Vec<usize>,
+}
+
+impl Match {
+    fn rank(&self) -> (u32, i64) {
+        (self.name_hits, self.score)
+    }
+
+    fn and(mut self, other: Match) -> Match {
+        self.name_hits += other.name_hits;
+        self.score += other.score;
+        self.indices.extend(other.indices);
+        self.indices.sort_unstable();
+        self.indices.dedup();
+        self
+    }
+}
+
+/// An indexed entry being tested against a query.
+pub struct Candidate<'a> {
+    pub record: &'a FileRecord,
+    pub relative: String,
+    name_byte: usize,
+    relative_lower: OnceCell<String>,
+}
+
+impl<'a> Candidate<'a> {
+    pub fn new(root: &Path, record: &'a FileRecord) -> Candidate<'a> {
+        let path = &record.path;
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned();
+        Candidate {
+            record,
+            name_byte: relative
+                .rfind(std::path::MAIN_SEPARATOR)
+                .map_or(0, |i| i + 1),
+            relative,
+            relative_lower: OnceCell::new(),
+        }
+    }
+
+    fn relative_lower(&self) -> &str {
+        self.relative_lower
+            .get_or_init(|| self.relative.to_lowercase())
+    }
+
+    fn fuzzy(&self, word: &str, matcher: &SkimMatcherV2) -> Option<Match> {
+        let name = &self.relative[self.name_byte..];
+        if let Some((score, indices)) = matcher.fuzzy_indices(name, word) {
+            let offset = self.relative[..self.name_byte].chars().count();
+            return Some(Match {
+                name_hits: 1,
+                score,
+                indices: indices.into_iter().map(|i| i + offset).collect(),
+            });
+        }
+        matcher
+            .fuzzy_indices(&self.relative, word)
+            .map(|(score, indices)| Match {
+                name_hits: 0,
+                score,
+                indices,
+            })
+    }
+
+    fn phrase(&self, phrase: &str) -> Option<Match> {
+        let lower = self.relative_lower();
+        let pos = lower.rfind(phrase)?;
+        let len = phrase.chars().count();
+        // lowercasing can change byte offsets outside ascii; skip highlighting then
+        let indices = if lower.len() == self.relative.len() {
+            let start = lower[..pos].chars().count();
+            (start..start + len).collect()
+        } else {
+            Vec::new()
+        };
+        Some(Match {
+            name_hits: (pos >= self.name_byte) as u32,
+            score: 16 * len as i64,
+            indices,
+        })
+    }
+
+    fn text(&self, field: Field) -> Option<String> {
+        let path = &self.record.path;
+        match field {
+            Field::Ext => path.extension().map(|x| x.to_string_lossy().to_lowercase()),
+            Field::Name => path.file_name().map(|x| x.to_string_lossy().to_lowercase()),
+            Field::Dir => Path::new(&self.relative)
+                .parent()
+                .map(|x| x.to_string_lossy().to_lowercase()),
+            Field::Format => self.record.format.clone(),
+            _ => None,
+        }
+    }
+
+    fn number(&self, field: Field) -> Option<f64> {
+        let r = self.record;
+        match field {
+            Field::Duration => r.duration,
+            Field::SampleRate => r.sample_rate.map(f64::from),
+            Field::Channels => r.channels.map(f64::from),
+            Field::BitDepth => r.bit_depth.map(f64::from),
+            Field::Size if !r.is_dir => Some(r.size as f64),
+            Field::Peak => r.peak,
+            Field::Rms => r.rms,
+            Field::Loudness => r.lufs,
+            Field::DcOffset => r.dc_offset,
+            Field::Bpm => r.bpm,
+            _ => None,
+        }
+    }
+
+    fn check(&self, field: Field, constraint: &Constraint) -> bool {
+        match constraint {
+            Constraint::Text(value) => match (field, self.text(field)) {
+                (Field::Ext, Some(ext)) => ext == *value,
+                (_, Some(text)) => text.contains(value.as_str()),
+                (_, None) => false,
+            },
+            Constraint::Key(key) => self.record.key == Some(*key),
+            Constraint::CompatibleKey(key) => self.record.key.is_some_and(|k| key.is_compatible(k)),
+            constraint => self
+                .number(field)
+                .is_some_and(|x| constraint.accepts(x, field.tolerance())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(w: &str) -> SearchDSL {
+        SearchDSL::Literal(w.to_owned())
+    }
+
+    fn parse_error(input: &str) -> String {
+        SearchDSL::parse(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            SearchDSL::parse("kick hard OR snare").unwrap(),
+            SearchDSL::Or(vec![
+                SearchDSL::And(vec![word("kick"), word("hard")]),
+                word("snare"),
+            ])
+        );
+        assert_eq!(
+            SearchDSL::parse("kick AND (hard | soft)").unwrap(),
+            SearchDSL::And(vec![
+                word("kick"),
+                SearchDSL::Or(vec![word("hard"), word("soft")]),
+            ])
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term_only() {
+        let not = |term| SearchDSL::Negate(Box::new(term));
+        assert_eq!(
+            SearchDSL::parse("NOT loop kick").unwrap(),
+            SearchDSL::And(vec![not(word("loop")), word("kick")])
+        );
+        assert_eq!(
+            SearchDSL::parse("-(a OR b)").unwrap(),
+            not(SearchDSL::Or(vec![word("a"), word("b")]))
+        );
+        assert_eq!(SearchDSL::parse("!a").unwrap(), not(word("a")));
+        // a dash inside a word is part of it
+        assert_eq!(SearchDSL::parse("hi-hat").unwrap(), word("hi-hat"));
+    }
+
+    #[test]
+    fn parses_quoted_phrases() {
+        assert_eq!(
+            SearchDSL::parse("\"Open Hat\" 808").unwrap(),
+            SearchDSL::And(vec![SearchDSL::Phrase("open hat".to_owned()), word("808"),])
+        );
+        assert_eq!(
+            SearchDSL::parse("dir:\"Drum Loops\"").unwrap(),
+            SearchDSL::Metadata(Field::Dir, Constraint::Text("drum loops".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parses_metadata_with_units() {
+        let term = |input| SearchDSL::parse(input).unwrap();
+        let metadata = SearchDSL::Metadata;
+        assert_eq!(
+            term("dur:<2s"),
+            metadata(Field::Duration, Constraint::Lt(2.0))
+        );
+        assert_eq!(
+            term("dur:>=500ms"),
+            metadata(Field::Duration, Constraint::Ge(0.5))
+        );
+        assert_eq!(
+            term("len:1m-2m"),
+            metadata(Field::Duration, Constraint::Range(60.0, 120.0))
+        );
+        assert_eq!(
+            term("sr:44100"),
+            metadata(Field::SampleRate, Constraint::Eq(44100.0))
+        );
+        assert_eq!(
+            term("sr:48k"),
+            metadata(Field::SampleRate, Constraint::Eq(48000.0))
+        );
+        assert_eq!(
+            term("ch:stereo"),
+            metadata(Field::Channels, Constraint::Eq(2.0))
+        );
+        assert_eq!(
+            term("ext:.WAV"),
+            metadata(Field::Ext, Constraint::Text("wav".to_owned()))
+        );
+        assert_eq!(
+            term("format:wav"),
+            metadata(Field::Ext, Constraint::Text("wav".to_owned()))
+        );
+        assert_eq!(
+            term("codec:flac"),
+            metadata(Field::Format, Constraint::Text("flac".to_owned()))
+        );
+        assert_eq!(
+            term("key:~Am"),
+            metadata(
+                Field::Key,
+                Constraint::CompatibleKey(Key::parse("Am").unwrap())
+            )
+        );
+    }
+
+    #[test]
+    fn reports_where_queries_go_wrong() {
+        assert_eq!(
+            parse_error("kick dur:abc"),
+            "column 6: `abc` is not a valid value for `dur`"
+        );
+        assert_eq!(parse_error("foo:1"), "column 1: unknown field `foo`");
+        assert_eq!(parse_error("dur:"), "column 1: expected a value after `:`");
+        assert_eq!(parse_error("key:H"), "column 1: `H` is not a key");
+        assert_eq!(parse_error("\"open hat"), "column 1: unterminated quote");
+        assert_eq!(parse_error("(kick snare"), "column 1: unclosed `(`");
+        assert_eq!(parse_error("kick)"), "column 5: unexpected `)`");
+        assert_eq!(
+            parse_error("café dur:x"),
+            "column 6: `x` is not a valid value for `dur`"
+        );
+        assert_eq!(parse_error("kick OR"), "column 8: expected a search term");
+        assert_eq!(
+            parse_error("OR kick"),
+            "column 1: `OR` needs a term on both sides"
+        );
+        assert_eq!(
+            parse_error("kick - snare"),
+            "column 6: `-` must be followed by a term"
+        );
+    }
+
+    #[test]
+    fn searches_nested_libraries_once() {
+        let libraries: Vec<Library> = ["/samples/drums", "/samples", "/loops", "/loops"]
+            .into_iter()
+            .map(|p| Library::new(PathBuf::from(p)))
+            .collect();
+        assert_eq!(
+            SearchScope::AllLibraries.roots(Path::new("/"), &libraries),
+            [
+                (PathBuf::from("/"), PathBuf::from("/samples")),
+                (PathBuf::from("/"), PathBuf::from("/loops")),
+            ]
+        );
+    }
 }
@@ -0,0 +1,76 @@
+pub use super::common::*;
+use super::{Colormap, FrequencyScale, Output, Spectrogram, WINDOW_SIZES};
+use iced::widget::{Column, Container, Row, Text, pick_list};
+use iced::{Element, Length};
+
+/// The settings side panel.
+pub struct Settings {
+    /// Output device names, listed when the panel opens.
+    pub devices: Vec<String>,
+}
+
+impl Settings {
+    /// `locked` when the output is set by the environment, which wins over
+    /// the configured one.
+    pub fn view<'a>(
+        &'a self,
+        output: &'a Output,
+        locked: bool,
+        spectrogram: &Spectrogram,
+    ) -> Element<'a, Message> {
+        let mut outputs = vec![Output::Default];
+        outputs.extend(self.devices.iter().cloned().map(Output::Device));
+        outputs.push(Output::Null);
+        if !outputs.contains(output) {
+            outputs.push(output.clone());
+        }
+        let picker = pick_list(outputs, Some(output.clone()), Message::OutputSelected)
+            .text_size(16)
+            .width(Length::Fill);
+        let column = Column::new()
+            .push(Text::new("Settings").size(20))
+            .push(Text::new("Output").size(16));
+        let column = if locked {
+            column.push(Text::new(format!("{} (set by TUNDRA_OUTPUT)", output)).size(16))
+        } else {
+            column.push(picker)
+        };
+        let spectrogram = *spectrogram;
+        let window = pick_list(WINDOW_SIZES, Some(spectrogram.window), move |window| {
+            Message::SpectrogramChanged(Spectrogram {
+                window,
+                ..spectrogram
+            })
+        });
+        let scale = pick_list(FrequencyScale::ALL, Some(spectrogram.scale), move |scale| {
+            Message::SpectrogramChanged(Spectrogram {
+                scale,
+                ..spectrogram
+            })
+        });
+        let colormap = pick_list(Colormap::ALL, Some(spectrogram.colormap), move |colormap| {
+            Message::SpectrogramChanged(Spectrogram {
+                colormap,
+                ..spectrogram
+            })
+        });
+        let column = column
+            .push(Text::new("Spectrogram").size(16))
+            .push(labelled("Window", window.text_size(16)))
+            .push(labelled("Scale", scale.text_size(16)))
+            .push(labelled("Colours", colormap.text_size(16)));
+        Container::new(column.spacing(6).padding(6))
+            .width(Length::Fixed(260.0))
+            .height(Length::Fill)
+            .into()
+    }
+}
+
+/// `control` with a label to its left.
+fn labelled<'a>(label: &'a str, control: impl Into<Element<'a, Message>>) -> Row<'a, Message> {
+    Row::new()
+        .push(Text::new(label).size(14).width(Length::Fixed(70.0)))
+        .push(control)
+        .spacing(6)
+        .align_y(iced::Alignment::Center)
+}
@@ -0,0 +1,110 @@
+use super::DirCache;
+use super::FileRecord;
+use super::search::{Hit, Match};
+use crate::analysis::timbre::{FEATURES, GROUPS};
+use std::path::{Path, PathBuf};
+
+/// How many neighbours "Find similar" lists.
+pub const SIMILAR_COUNT: usize = 50;
+
+/// An analysed file: the directory its hit is labelled relative to (the
+/// `base` from `SearchScope::roots`), its record and its timbre vector.
+type Entry = (PathBuf, FileRecord, Vec<f32>);
+
+/// The timbre vectors of every analysed file below a set of roots. Each
+/// dimension is standardised over the whole set before comparing, and the
+/// feature groups are weighted so that e.g. the 24 MFCC values do not
+/// drown out duration.
+pub struct SimilarityIndex {
+    entries: Vec<Entry>,
+    /// Multiplies the squared difference in each dimension.
+    scale: Vec<f32>,
+}
+
+impl SimilarityIndex {
+    /// Loads the vectors below each `(base, root)` pair, see
+    /// `SearchScope::roots`.
+    pub fn load(dir_cache: &DirCache, roots: &[(PathBuf, PathBuf)]) -> rusqlite::Result<Self> {
+        let mut entries = Vec::new();
+        for (base, root) in roots {
+            entries.extend(
+                dir_cache
+                    .features_under(root)?
+                    .into_iter()
+                    // vectors written by a build with a different layout
+                    .filter(|(_, features)| features.len() == FEATURES)
+                    .map(|(record, features)| (base.clone(), record, features)),
+            );
+        }
+        let n = entries.len().max(1) as f32;
+        let mut mean = vec![0.0; FEATURES];
+        for (_, _, features) in &entries {
+            mean.iter_mut().zip(features).for_each(|(m, x)| *m += x / n);
+        }
+        let mut variance = [0.0; FEATURES];
+        for (_, _, features) in &entries {
+            for ((v, m), x) in variance.iter_mut().zip(&mean).zip(features) {
+                *v += (x - m).powi(2) / n;
+            }
+        }
+        let weights = GROUPS
+            .iter()
+            .flat_map(|&len| std::iter::repeat_n(1.0 / len as f32, len));
+        let scale = variance
+            .iter()
+            .zip(weights)
+            .map(|(&v, w)| if v > 0.0 { w / v } else { 0.0 })
+            .collect();
+        Ok(SimilarityIndex { entries, scale })
+    }
+
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        a.iter()
+            .zip(b)
+            .zip(&self.scale)
+            .map(|((x, y), s)| s * (x - y).powi(2))
+            .sum()
+    }
+
+    /// The `count` entries closest to `features`, nearest first, leaving out
+    /// `exclude` itself.
+    pub fn nearest(&self, features: &[f32], exclude: &Path, count: usize) -> Vec<Hit> {
+        let mut scored: Vec<(f32, &Entry)> = self
+            .entries
+            .iter()
+            .filter(|(_, record, _)| record.path != exclude)
+            .map(|entry| (self.distance(features, &entry.2), entry))
+            .collect();
+        if scored.len() > count {
+            scored.select_nth_unstable_by(count, |a, b| a.0.total_cmp(&b.0));
+            scored.truncate(count);
+        }
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+        scored
+            .into_iter()
+            .map(|(_, (base, record, _))| Hit {
+                path: record.path.clone(),
+                base: base.clone(),
+                record: record.clone(),
+                m: Match::default(),
+            })
+            .collect()
+    }
+}
+
+/// Looks up `target`'s features and finds its nearest neighbours below
+/// `roots`.
+pub fn find_similar(target: &Path, roots: &[(PathBuf, PathBuf)]) -> Result<Vec<Hit>, String> {
+    let dir_cache = DirCache::open().map_err(|e| e.to_string())?;
+    let features = match dir_cache.features(target).map_err(|e| e.to_string())? {
+        Some(features) if features.len() == FEATURES => features,
+        _ => {
+            return Err(format!(
+                "{} has not been analysed yet",
+                target.file_name().unwrap_or_default().to_string_lossy()
+            ));
+        }
+    };
+    let index = SimilarityIndex::load(&dir_cache, roots).map_err(|e| e.to_string())?;
+    Ok(index.nearest(&features, target, SIMILAR_COUNT))
+}
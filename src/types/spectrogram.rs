@@ -0,0 +1,208 @@
+use crate::analysis::fft::Fft;
+use crate::analysis::timbre::{hz_to_mel, mel_to_hz};
+use iced::widget::canvas::Frame;
+use iced::{Color, Point, Rectangle, Size};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// STFT window sizes that can be picked, in samples.
+pub const WINDOW_SIZES: [usize; 6] = [256, 512, 1024, 2048, 4096, 8192];
+/// Pixels per side of a cell, each of which is one bin of one frame.
+const CELL: f32 = 2.0;
+/// Bottom of the logarithmic scale.
+const LOW_HZ: f32 = 20.0;
+/// Levels at and below this many dBFS are drawn as silence.
+const FLOOR_DB: f32 = -100.0;
+/// Shades per colour map, so neighbouring cells that look the same are
+/// drawn as one rectangle.
+const SHADES: f32 = 64.0;
+
+/// How frequency maps to height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrequencyScale {
+    Linear,
+    #[default]
+    Log,
+    Mel,
+}
+
+impl FrequencyScale {
+    pub const ALL: [FrequencyScale; 3] = [
+        FrequencyScale::Linear,
+        FrequencyScale::Log,
+        FrequencyScale::Mel,
+    ];
+
+    /// The frequency `t` (0 to 1) of the way up to `nyquist`.
+    fn hz(self, t: f32, nyquist: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => t * nyquist,
+            FrequencyScale::Log => LOW_HZ * (nyquist / LOW_HZ).powf(t),
+            FrequencyScale::Mel => mel_to_hz(t * hz_to_mel(nyquist)),
+        }
+    }
+}
+
+impl fmt::Display for FrequencyScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FrequencyScale::Linear => "Linear",
+            FrequencyScale::Log => "Logarithmic",
+            FrequencyScale::Mel => "Mel",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Colormap {
+    #[default]
+    Magma,
+    Viridis,
+    Grayscale,
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 3] = [Colormap::Magma, Colormap::Viridis, Colormap::Grayscale];
+
+    fn stops(self) -> &'static [(u8, u8, u8)] {
+        match self {
+            Colormap::Magma => &[
+                (0x00, 0x00, 0x04),
+                (0x3b, 0x0f, 0x70),
+                (0x8c, 0x29, 0x81),
+                (0xde, 0x49, 0x68),
+                (0xfe, 0x9f, 0x6d),
+                (0xfc, 0xfd, 0xbf),
+            ],
+            Colormap::Viridis => &[
+                (0x44, 0x01, 0x54),
+                (0x3b, 0x52, 0x8b),
+                (0x21, 0x91, 0x8c),
+                (0x5e, 0xc9, 0x62),
+                (0xfd, 0xe7, 0x25),
+            ],
+            Colormap::Grayscale => &[(0x00, 0x00, 0x00), (0xff, 0xff, 0xff)],
+        }
+    }
+
+    /// The colour of `t`, from 0 (silence) to 1 (full scale).
+    fn color(self, t: f32) -> Color {
+        let stops = self.stops();
+        let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let i = (position as usize).min(stops.len() - 2);
+        let (a, b) = (stops[i], stops[i + 1]);
+        let mix = |a: u8, b: u8| {
+            let (a, b) = (a as f32 / 255.0, b as f32 / 255.0);
+            a + (b - a) * (position - i as f32)
+        };
+        Color::from_rgb(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+    }
+}
+
+impl fmt::Display for Colormap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Colormap::Magma => "Magma",
+            Colormap::Viridis => "Viridis",
+            Colormap::Grayscale => "Grayscale",
+        })
+    }
+}
+
+/// How the spectrogram is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Spectrogram {
+    /// STFT window size in samples, one of `WINDOW_SIZES`.
+    pub window: usize,
+    pub scale: FrequencyScale,
+    pub colormap: Colormap,
+}
+
+impl Default for Spectrogram {
+    fn default() -> Spectrogram {
+        Spectrogram {
+            window: 2048,
+            scale: FrequencyScale::default(),
+            colormap: Colormap::default(),
+        }
+    }
+}
+
+impl Spectrogram {
+    /// Draws the spectrogram of `samples` into `area`, a column of cells
+    /// per frame centred on each `CELL` pixels from the sample `first` on.
+    /// Stops where the samples run out.
+    pub fn draw(
+        &self,
+        frame: &mut Frame,
+        area: Rectangle,
+        samples: &[f32],
+        sample_rate: u32,
+        first: f64,
+        samples_per_pixel: f64,
+    ) {
+        let window = if WINDOW_SIZES.contains(&self.window) {
+            self.window
+        } else {
+            Spectrogram::default().window
+        };
+        let mut fft = Fft::new(window);
+        let nyquist = sample_rate as f32 / 2.0;
+        let bin_hz = sample_rate as f32 / window as f32;
+        let rows = (area.height / CELL).ceil() as usize;
+        // the bins each row covers, bottom up; rows narrower than a bin show
+        // the nearest one
+        let bins: Vec<(usize, usize)> = (0..rows)
+            .map(|row| {
+                let low = self.scale.hz(row as f32 / rows as f32, nyquist) / bin_hz;
+                let high = self.scale.hz((row + 1) as f32 / rows as f32, nyquist) / bin_hz;
+                let low = (low.round() as usize).min(window / 2);
+                (low, (high.round() as usize).clamp(low + 1, window / 2 + 1))
+            })
+            .collect();
+        // a full scale sine peaks at a quarter of the window through Hann
+        let full = window as f32 / 4.0;
+        let mut input = vec![0.0; window];
+        for column in 0..(area.width / CELL).ceil() as usize {
+            let x = column as f32 * CELL;
+            let centre = first + (x + CELL / 2.0) as f64 * samples_per_pixel;
+            if centre as usize >= samples.len() {
+                break;
+            }
+            let start = centre as i64 - window as i64 / 2;
+            for (i, value) in input.iter_mut().enumerate() {
+                *value = usize::try_from(start + i as i64)
+                    .ok()
+                    .and_then(|i| samples.get(i).copied())
+                    .unwrap_or(0.0);
+            }
+            let magnitudes = fft.magnitudes(&input);
+            let shades = bins.iter().map(|&(low, high)| {
+                let magnitude = magnitudes[low..high].iter().fold(0.0f32, |a, &m| a.max(m));
+                let db = 20.0 * (magnitude / full).max(1e-10).log10();
+                ((1.0 - db / FLOOR_DB).clamp(0.0, 1.0) * SHADES).round()
+            });
+            // runs of the same shade go in one rectangle
+            let mut run: Option<(usize, f32)> = None;
+            for (row, shade) in shades.chain([f32::NAN]).enumerate() {
+                match run {
+                    Some((_, current)) if current == shade => continue,
+                    Some((from, current)) => {
+                        let bottom = area.y + area.height - from as f32 * CELL;
+                        let height = ((row - from) as f32 * CELL).min(bottom - area.y);
+                        frame.fill_rectangle(
+                            Point::new(area.x + x, bottom - height),
+                            Size::new(CELL, height),
+                            self.colormap.color(current / SHADES),
+                        );
+                    }
+                    None => (),
+                }
+                run = Some((row, shade));
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+pub use super::common::*;
+use super::{FileList, FileRecord, IndexUpdate};
+use futures::channel::mpsc::{Sender, unbounded};
+use futures::{SinkExt, StreamExt};
+use iced::Subscription;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Events arriving within this window of each other are applied together.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+/// Upper bound on how long a steady stream of events (e.g. a large copy)
+/// can hold back an update.
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// Watches every indexed root and reports changes as `Message::IndexChanged`.
+/// The subscription is keyed on `roots`, so it restarts when they change.
+pub fn watch(roots: &[PathBuf]) -> Subscription<Message> {
+    let roots = roots.to_vec();
+    Subscription::run_with_id(
+        ("watcher", roots.clone()),
+        iced::stream::channel(16, move |output| watch_roots(roots, output)),
+    )
+}
+
+async fn watch_roots(roots: Vec<PathBuf>, mut output: Sender<Message>) {
+    if roots.is_empty() {
+        return;
+    }
+    let (events_tx, events_rx) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(events_tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("could not start the file watcher: {}", e);
+            return;
+        }
+    };
+    for root in &roots {
+        if let Err(e) = watcher.watch(root, RecursiveMode::Recursive) {
+            eprintln!("could not watch {}: {}", root.display(), e);
+        }
+    }
+    let (updates_tx, mut updates_rx) = unbounded();
+    // scanning touches the disk, keep it off the executor
+    thread::spawn(move || {
+        let _watcher = watcher;
+        loop {
+            let first = match events_rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) if updates_tx.is_closed() => break,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let mut changes = Changes::default();
+            changes.add(first);
+            let started = Instant::now();
+            while started.elapsed() < MAX_DELAY {
+                match events_rx.recv_timeout(DEBOUNCE) {
+                    Ok(event) => changes.add(event),
+                    Err(_) => break,
+                }
+            }
+            let update = changes.scan(&roots);
+            if !update.is_empty() && updates_tx.unbounded_send(update).is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(update) = updates_rx.next().await {
+        if output.send(Message::IndexChanged(update)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Removed,
+    Changed,
+}
+
+/// The latest change seen for each path in a debounce window.
+#[derive(Default)]
+struct Changes(HashMap<PathBuf, Change>);
+
+impl Changes {
+    fn add(&mut self, event: notify::Result<Event>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("file watcher: {}", e);
+                return;
+            }
+        };
+        let mut paths = event.paths.into_iter();
+        match event.kind {
+            EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Modify(ModifyKind::Any)
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                paths.for_each(|p| self.set(p, Change::Changed))
+            }
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                paths.for_each(|p| self.set(p, Change::Removed))
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let Some(from) = paths.next() {
+                    self.set(from, Change::Removed);
+                }
+                paths.for_each(|to| self.set(to, Change::Changed));
+            }
+            // the backend could not tell which side of a rename this is
+            EventKind::Modify(ModifyKind::Name(_)) => paths.for_each(|p| {
+                let change = if p.exists() {
+                    Change::Changed
+                } else {
+                    Change::Removed
+                };
+                self.set(p, change)
+            }),
+            _ => (),
+        }
+    }
+
+    fn set(&mut self, path: PathBuf, change: Change) {
+        self.0.insert(path, change);
+    }
+
+    fn scan(self, roots: &[PathBuf]) -> IndexUpdate {
+        let mut update = IndexUpdate::default();
+        for (path, change) in self.0 {
+            if !is_indexable(&path, roots) {
+                continue;
+            }
+            match change {
+                Change::Removed => update.removed.push(path),
+                Change::Changed if path.is_dir() => update.upserted.extend(FileRecord::walk(&path)),
+                Change::Changed => match FileRecord::scan(&path) {
+                    Some(record) => update.upserted.push(record),
+                    None => update.removed.push(path),
+                },
+            }
+        }
+        update
+    }
+}
+
+/// Applies the file list's filter to every component below the watched root,
+/// so nothing inside hidden directories gets indexed.
+fn is_indexable(path: &Path, roots: &[PathBuf]) -> bool {
+    let relative = match roots.iter().find_map(|root| path.strip_prefix(root).ok()) {
+        Some(relative) => relative,
+        None => return false,
+    };
+    if relative
+        .iter()
+        .any(|c| c.to_string_lossy().starts_with('.'))
+    {
+        return false;
+    }
+    !path.exists() || FileList::file_filter(path.to_path_buf())
+}
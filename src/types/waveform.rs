@@ -1,64 +1,438 @@
 pub use super::common::*;
 
-use iced::keyboard::Key;
+use super::{Bucket, Peaks, Spectrogram};
+use crate::source::decoded::{Decoded, DecodedSource};
+use futures::channel::mpsc::{UnboundedSender, unbounded};
+use futures::{SinkExt, Stream, StreamExt};
+use iced::keyboard::Modifiers;
+use iced::mouse;
 use iced::mouse::Cursor;
 use iced::widget::canvas::*;
-use iced::{Color, Point, Rectangle, Renderer, Theme};
-use rodio::Source;
-use std::fs::File;
+use iced::{Color, Point, Rectangle, Renderer, Size, Theme};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+/// Frames per `WaveFormChunk`.
+const CHUNK_LEN: usize = 1 << 18;
+
+/// Scrubbing seeks at most this often. Seeks within the decoded file are
+/// cheap, but each one restarts the sink, so a position needs to play for a
+/// moment to be heard.
+const SCRUB_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Each wheel notch or zoom key zooms by this much.
+const ZOOM_STEP: f32 = 1.25;
+/// Zooming in stops with this many samples in view.
+const MIN_VISIBLE: f64 = 64.0;
+/// A wheel notch pans by this much of the view.
+const PAN_STEP: f32 = 0.1;
+/// Wheels that report pixels count this many as a notch.
+const PIXELS_PER_NOTCH: f32 = 50.0;
+/// Height of the strip along the bottom that shows the whole file.
+const OVERVIEW_HEIGHT: f32 = 28.0;
+/// The part of the height the waveform keeps when the spectrogram is shown.
+const WAVEFORM_SHARE: f32 = 0.4;
+
+#[derive(Default)]
 pub struct WaveFormState {
-    zoom: f32,
-    scroll: f32,
+    modifiers: Modifiers,
+    drag: Option<Drag>,
+    /// Where the cursor was on the last move of a middle-button pan.
+    pan: Option<f32>,
+    /// Set while the view is being dragged around in the overview.
+    overview_drag: bool,
 }
 
-impl Default for WaveFormState {
-    fn default() -> WaveFormState {
-        WaveFormState {
-            zoom: 1.0,
-            scroll: 0.0,
+/// A mouse drag over the waveform, positions in samples.
+struct Drag {
+    anchor: u64,
+    current: u64,
+    /// Shift-drags select a region to loop instead of scrubbing.
+    select: bool,
+    /// Where playback was last moved to, and when.
+    sought: u64,
+    sought_at: Instant,
+}
+
+/// How the channels of a file are laid out in the waveform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lanes {
+    /// A lane per channel.
+    #[default]
+    Channels,
+    /// The channels mixed into one.
+    Sum,
+    /// Mid and side, for stereo files. Others show their channels.
+    MidSide,
+}
+
+impl Lanes {
+    pub const ALL: [Lanes; 3] = [Lanes::Channels, Lanes::Sum, Lanes::MidSide];
+
+    pub fn next(self) -> Lanes {
+        match self {
+            Lanes::Channels => Lanes::Sum,
+            Lanes::Sum => Lanes::MidSide,
+            Lanes::MidSide => Lanes::Channels,
         }
     }
 }
 
-pub struct WaveForm {
-    pub samples: Vec<i16>,
-    pub bits_per_sample: u32,
+impl fmt::Display for Lanes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Lanes::Channels => "Channels",
+            Lanes::Sum => "Summed",
+            Lanes::MidSide => "Mid / side",
+        })
+    }
+}
+
+/// How a file stores its samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Signed integers of this many bits.
+    Int(u16),
+    /// Floats of this many bits.
+    Float(u16),
+    /// A lossy codec, which has no bit depth of its own.
+    Lossy,
+}
+
+/// The layout of a file's audio, as its header gives it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub channels: u16,
     pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sample_format {
+            SampleFormat::Int(bits) => write!(f, "{} bit", bits)?,
+            SampleFormat::Float(bits) => write!(f, "{} bit float", bits)?,
+            SampleFormat::Lossy => f.write_str("Lossy")?,
+        }
+        write!(f, ", {} kHz, ", self.sample_rate as f32 / 1000.0)?;
+        match self.channels {
+            1 => f.write_str("mono"),
+            2 => f.write_str("stereo"),
+            channels => write!(f, "{} channels", channels),
+        }
+    }
+}
+
+pub struct WaveForm {
+    pub format: Format,
+    /// The sum of the channels, full scale being 1. The spectrogram is drawn
+    /// from it, so it is the only signal kept at full resolution.
+    samples: Vec<f32>,
+    /// The peaks of each signal: the sum, then each channel, then the side
+    /// of stereo files (whose mid is the sum). Mono files only have the sum.
+    peaks: Vec<Peaks>,
+    lanes: Lanes,
+    /// Drawn below the waveform if set.
+    spectrogram: Option<Spectrogram>,
+    /// How many samples the file should decode to, so a waveform that is
+    /// still loading fills in from the left instead of stretching.
+    pub expected_len: Option<usize>,
+    /// Playback position in samples, drawn over the waveform.
+    pub playhead: u64,
+    /// The region being looped, in samples.
+    pub selection: Option<(u64, u64)>,
+    /// Horizontal magnification, 1 fits the whole file.
+    zoom: f32,
+    /// The first sample in view.
+    scroll: f64,
     cache: Cache,
+    overview: Cache,
+}
+
+/// A piece of a waveform decoded in the background.
+#[derive(Debug, Clone)]
+pub struct WaveFormChunk {
+    pub path: PathBuf,
+    pub expected_len: Option<usize>,
+    pub format: Format,
+    /// Cached peaks of each signal, sent ahead of the samples.
+    pub peaks: Option<Arc<Vec<Peaks>>>,
+    /// A run of each signal, which only the sum is kept of once its peaks
+    /// are taken.
+    pub samples: Vec<Vec<f32>>,
+    /// Set on the last chunk.
+    pub done: bool,
 }
 
 impl WaveForm {
-    pub fn to_path(&self, state: &WaveFormState, frame: &Frame) -> Path {
-        let max = 2_i32.pow(self.bits_per_sample);
-        let translate_y = (max / 2) as f32;
-        let _translate_x = state.scroll;
-        let height = frame.height();
-        let width = frame.width();
-        let truncate = 1; // (self.samples.len() as usize).div(width as usize);
-        let scale_height = height / max as f32;
-        let scale_width = (width / self.samples.len() as f32) * (truncate as f32) * state.zoom;
-        let mut builder = path::Builder::new();
-        let mut old_y: f32 = translate_y * scale_height;
-        self.samples
-            .chunks(truncate)
-            .map(|x| x.iter().max_by_key(|y| y.abs()).unwrap_or(&0))
-            .enumerate()
-            .for_each(|(i, s)| {
-                let sample = s.to_owned() as f32;
-                let x = i as f32 * scale_width;
-                let h_point = Point { x, y: old_y };
-                let y = (sample + translate_y) * scale_height;
-                old_y = y;
-                let v_point = Point { x, y };
-                builder.line_to(h_point);
-                builder.move_to(h_point);
-                builder.line_to(v_point);
-                builder.move_to(v_point);
+    pub fn new(expected_len: Option<usize>, format: Format, lanes: Lanes) -> WaveForm {
+        WaveForm {
+            format,
+            samples: Vec::with_capacity(expected_len.unwrap_or(0)),
+            peaks: vec![Peaks::new(); signal_count(format.channels)],
+            lanes,
+            spectrogram: None,
+            expected_len,
+            playhead: 0,
+            selection: None,
+            zoom: 1.0,
+            scroll: 0.0,
+            cache: Cache::new(),
+            overview: Cache::new(),
+        }
+    }
+
+    /// Zooms in `steps` (out if negative), keeping the sample `at` (0 to 1)
+    /// of the way across the view where it is.
+    pub fn zoom_at(&mut self, steps: f32, at: f32) {
+        let anchor = self.scroll + at as f64 * self.visible();
+        self.zoom *= ZOOM_STEP.powf(steps);
+        self.clamp_view();
+        self.scroll = anchor - at as f64 * self.visible();
+        self.clamp_view();
+    }
+
+    /// Moves the view right by `by` of its width, left if negative.
+    pub fn pan(&mut self, by: f32) {
+        self.scroll += by as f64 * self.visible();
+        self.clamp_view();
+    }
+
+    /// Centres the view on the sample `at` (0 to 1) of the way through.
+    pub fn scroll_to(&mut self, at: f32) {
+        self.scroll = at as f64 * self.len() as f64 - self.visible() / 2.0;
+        self.clamp_view();
+    }
+
+    /// Fits the selection to the view, or the whole file without one.
+    pub fn zoom_to_selection(&mut self) {
+        let (start, end) = self.selection.unwrap_or((0, self.len() as u64));
+        self.zoom = (self.len() as f64 / end.saturating_sub(start).max(1) as f64) as f32;
+        self.clamp_view();
+        self.scroll = start as f64;
+        self.clamp_view();
+    }
+
+    fn clamp_view(&mut self) {
+        let len = self.len().max(1) as f64;
+        self.zoom = self.zoom.clamp(1.0, (len / MIN_VISIBLE).max(1.0) as f32);
+        self.scroll = self.scroll.clamp(0.0, len - self.visible());
+        self.cache.clear();
+    }
+
+    /// Samples in view.
+    fn visible(&self) -> f64 {
+        self.len().max(1) as f64 / self.zoom as f64
+    }
+
+    pub fn set_lanes(&mut self, lanes: Lanes) {
+        self.lanes = lanes;
+        self.cache.clear();
+    }
+
+    pub fn set_spectrogram(&mut self, spectrogram: Option<Spectrogram>) {
+        self.spectrogram = spectrogram;
+        self.cache.clear();
+    }
+
+    /// Appends a run of each signal.
+    pub fn extend(&mut self, samples: &[Vec<f32>]) {
+        let start = self.samples.len();
+        if let Some(sum) = samples.first() {
+            self.samples.extend_from_slice(sum);
+        }
+        for (peaks, samples) in self.peaks.iter_mut().zip(samples) {
+            // cached peaks are already complete
+            if (start..start + samples.len()).contains(&peaks.len) {
+                peaks.extend(&samples[peaks.len - start..]);
+            }
+        }
+        self.cache.clear();
+        self.overview.clear();
+    }
+
+    /// Uses the peaks of the whole file, from the cache, while the samples
+    /// load.
+    pub fn set_peaks(&mut self, peaks: Vec<Peaks>) {
+        if peaks.len() != self.peaks.len() {
+            return;
+        }
+        self.expected_len = peaks.first().map(|peaks| peaks.len);
+        self.peaks = peaks;
+        self.cache.clear();
+        self.overview.clear();
+    }
+
+    /// Reads `path` on its own thread, yielding its signals a chunk at a
+    /// time. Wav and flac files are read at their own resolution; others
+    /// follow the decoding of `audio`, their contents as played.
+    pub fn load(path: PathBuf, audio: Arc<Decoded>) -> impl Stream<Item = WaveFormChunk> {
+        iced::stream::channel(4, move |mut output| async move {
+            let (chunks_tx, mut chunks_rx) = unbounded();
+            thread::spawn(move || {
+                let extension = path.extension().map(|x| x.to_string_lossy().to_lowercase());
+                match extension.as_deref() {
+                    Some("wav") => {
+                        if let Ok(reader) = hound::WavReader::open(&path) {
+                            let spec = reader.spec();
+                            let bits = spec.bits_per_sample;
+                            let len = Some(reader.duration() as usize);
+                            let format = |sample_format| Format {
+                                channels: spec.channels,
+                                sample_rate: spec.sample_rate,
+                                sample_format,
+                            };
+                            match spec.sample_format {
+                                hound::SampleFormat::Float => {
+                                    let format = format(SampleFormat::Float(bits));
+                                    let samples = reader.into_samples().map_while(Result::ok);
+                                    send_chunks(&path, format, len, samples, &chunks_tx);
+                                }
+                                hound::SampleFormat::Int => {
+                                    let format = format(SampleFormat::Int(bits));
+                                    let scale = full_scale(bits);
+                                    let samples = reader
+                                        .into_samples::<i32>()
+                                        .map_while(Result::ok)
+                                        .map(|s| s as f32 / scale);
+                                    send_chunks(&path, format, len, samples, &chunks_tx);
+                                }
+                            }
+                            return;
+                        }
+                    }
+                    Some("flac") => {
+                        if let Ok(mut reader) = claxon::FlacReader::open(&path) {
+                            let info = reader.streaminfo();
+                            let bits = info.bits_per_sample as u16;
+                            let format = Format {
+                                channels: info.channels as u16,
+                                sample_rate: info.sample_rate,
+                                sample_format: SampleFormat::Int(bits),
+                            };
+                            let len = info.samples.map(|n| n as usize);
+                            let scale = full_scale(bits);
+                            let samples = reader
+                                .samples()
+                                .map_while(Result::ok)
+                                .map(|s| s as f32 / scale);
+                            return send_chunks(&path, format, len, samples, &chunks_tx);
+                        }
+                    }
+                    _ => (),
+                }
+                let format = Format {
+                    channels: audio.channels,
+                    sample_rate: audio.sample_rate,
+                    sample_format: SampleFormat::Lossy,
+                };
+                let len = audio.expected_frames;
+                let samples = DecodedSource::new(audio, 0).map(|s| s as f32 / 32768.0);
+                send_chunks(&path, format, len, samples, &chunks_tx);
             });
+            while let Some(chunk) = chunks_rx.next().await {
+                if output.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
 
-        builder.close();
-        builder.build()
+    fn samples_per_pixel(&self, width: f32) -> f64 {
+        self.visible() / width.max(1.0) as f64
+    }
+
+    /// Where `sample` is drawn, in canvas coordinates.
+    fn x_of(&self, sample: u64, width: f32) -> f32 {
+        ((sample as f64 - self.scroll) / self.samples_per_pixel(width)) as f32
+    }
+
+    /// The sample under `x`, in canvas coordinates.
+    fn sample_at(&self, width: f32, x: f32) -> u64 {
+        let sample = self.scroll + x.max(0.0) as f64 * self.samples_per_pixel(width);
+        (sample as u64).min(self.len().saturating_sub(1) as u64)
+    }
+
+    /// The signals to draw in lanes, top to bottom, with their labels.
+    fn lanes(&self) -> Vec<(usize, String)> {
+        let signal = |i: usize, label: &str| (i, label.to_owned());
+        match (self.lanes, self.format.channels) {
+            (Lanes::Sum, _) | (_, 0 | 1) => vec![signal(0, "")],
+            (Lanes::MidSide, 2) => vec![signal(0, "M"), signal(3, "S")],
+            (_, 2) => vec![signal(1, "L"), signal(2, "R")],
+            (_, channels) => (1..=channels as usize)
+                .map(|i| signal(i, &i.to_string()))
+                .collect(),
+        }
+    }
+
+    /// The outlines of the min/max and RMS envelopes of `signal` in `area`,
+    /// one column per pixel, from the sample `first` on.
+    fn envelopes(
+        &self,
+        signal: usize,
+        area: Rectangle,
+        first: f64,
+        samples_per_pixel: f64,
+    ) -> (Path, Path) {
+        let middle = area.y + area.height / 2.0;
+        let y = |sample: f32| middle - sample * area.height / 2.0;
+        let len = self.len();
+        let level = Peaks::level_for(samples_per_pixel as f32);
+        // close-ups of the other signals make do with their base buckets
+        let samples: &[f32] = if signal == 0 { &self.samples } else { &[] };
+        let columns: Vec<(f32, Bucket)> = (0..area.width.ceil() as usize)
+            .map(|x| {
+                let start = (first + x as f64 * samples_per_pixel) as usize;
+                let end = (first + (x + 1) as f64 * samples_per_pixel) as usize;
+                let end = end.max(start + 1).min(len);
+                (
+                    area.x + x as f32,
+                    self.peaks[signal].range(level, start, end, samples),
+                )
+            })
+            // the rest has not been decoded yet
+            .take_while(|(_, bucket)| !bucket.is_empty())
+            .collect();
+        let outline = |top: &dyn Fn(&Bucket) -> f32, bottom: &dyn Fn(&Bucket) -> f32| {
+            Path::new(|builder| {
+                for (x, bucket) in &columns {
+                    builder.line_to(Point {
+                        x: *x,
+                        y: y(top(bucket)),
+                    });
+                }
+                for (x, bucket) in columns.iter().rev() {
+                    builder.line_to(Point {
+                        x: *x,
+                        y: y(bottom(bucket)),
+                    });
+                }
+                builder.close();
+            })
+        };
+        (
+            outline(&|b| b.max, &|b| b.min),
+            outline(&|b| b.rms(), &|b| -b.rms()),
+        )
+    }
+
+    /// Frames in the whole file, as far as is known.
+    pub fn len(&self) -> usize {
+        self.expected_len
+            .unwrap_or(0)
+            .max(self.samples.len())
+            .max(self.peaks[0].len)
+    }
+
+    /// Frames decoded so far.
+    pub fn decoded_len(&self) -> usize {
+        self.samples.len()
     }
 }
 
@@ -72,83 +446,356 @@ impl Program<Message> for WaveForm {
         bounds: Rectangle,
         _cursor: Cursor,
     ) -> Vec<Geometry> {
-        self.cache.clear();
+        let width = bounds.width;
+        let main = Rectangle::new(Point::ORIGIN, Size::new(width, main_height(bounds)));
+        let strip = Rectangle::new(
+            Point::new(0.0, main.height),
+            Size::new(width, bounds.height - main.height),
+        );
+        let (waves, spectrum) = match self.spectrogram {
+            Some(spectrogram) => {
+                let height = (main.height * WAVEFORM_SHARE).round();
+                let spectrum = Rectangle {
+                    y: main.y + height,
+                    height: main.height - height,
+                    ..main
+                };
+                (Rectangle { height, ..main }, Some((spectrogram, spectrum)))
+            }
+            None => (main, None),
+        };
+        let color = Color::from_rgb8(0x50, 0x7a, 0xe0);
         let geometry = self.cache.draw(renderer, bounds.size(), |frame| {
-            // frame.scale(0.01);
-            // frame.translate(Vector {
-            //     x: 0.0,
-            //     y: (max / 2) as f32
-            // });
-            let path = self.to_path(state, frame);
-            let stroke = Stroke {
-                width: 2.0,
-                line_cap: Default::default(),
-                line_join: Default::default(),
-                line_dash: Default::default(),
-                style: Style::Solid(Color::from_rgb8(0x50, 0x7a, 0xe0)),
-            };
-            frame.stroke(&path, stroke);
+            let per_pixel = self.samples_per_pixel(width);
+            if let Some((spectrogram, area)) = spectrum {
+                let samples = &self.samples;
+                let rate = self.format.sample_rate;
+                spectrogram.draw(frame, area, samples, rate, self.scroll, per_pixel);
+            }
+            let lanes = self.lanes();
+            let height = waves.height / lanes.len() as f32;
+            let separator = Stroke::default()
+                .with_width(1.0)
+                .with_color(Color::from_rgb8(0x40, 0x44, 0x4c));
+            for (i, (signal, label)) in lanes.into_iter().enumerate() {
+                let area =
+                    Rectangle::new(Point::new(0.0, i as f32 * height), Size::new(width, height));
+                let (peaks, rms) = self.envelopes(signal, area, self.scroll, per_pixel);
+                frame.fill(&peaks, color);
+                // so stretches of near silence still show as a line
+                frame.stroke(&peaks, Stroke::default().with_width(1.0).with_color(color));
+                frame.fill(&rms, Color::from_rgb8(0x8c, 0xa8, 0xee));
+                if i > 0 {
+                    let line = Path::line(area.position(), Point::new(width, area.y));
+                    frame.stroke(&line, separator);
+                }
+                frame.fill_text(Text {
+                    content: label,
+                    position: Point::new(area.x + 4.0, area.y + 2.0),
+                    color: Color::from_rgb8(0x90, 0x98, 0xa8),
+                    size: 12.0.into(),
+                    ..Text::default()
+                });
+            }
+        });
+        let overview = self.overview.draw(renderer, bounds.size(), |frame| {
+            let background = Color::from_rgb8(0x20, 0x24, 0x2c);
+            frame.fill_rectangle(strip.position(), strip.size(), background);
+            let per_pixel = self.len() as f64 / width.max(1.0) as f64;
+            let (peaks, _) = self.envelopes(0, strip, 0.0, per_pixel);
+            frame.fill(&peaks, Color::from_rgb8(0x6a, 0x76, 0x90));
         });
-        vec![geometry]
+        // drawn apart from the cached waveform, which only changes with the view
+        let mut playhead = Frame::new(renderer, bounds.size());
+        let selection = match &state.drag {
+            Some(drag) if drag.select => {
+                Some((drag.anchor.min(drag.current), drag.anchor.max(drag.current)))
+            }
+            _ => self.selection,
+        };
+        if let Some((start, end)) = selection {
+            let (start, end) = (self.x_of(start, width), self.x_of(end, width));
+            playhead.fill_rectangle(
+                Point { x: start, y: 0.0 },
+                Size::new(end - start, main.height),
+                Color::from_rgba8(0x50, 0x7a, 0xe0, 0.25),
+            );
+        }
+        let x = self.x_of(self.playhead, width);
+        let playhead_stroke = Stroke::default()
+            .with_width(1.5)
+            .with_color(Color::from_rgb8(0xe0, 0x6c, 0x50));
+        playhead.stroke(
+            &Path::line(Point { x, y: 0.0 }, Point { x, y: main.height }),
+            playhead_stroke,
+        );
+        // the overview marks the part in view, and the playhead
+        let len = self.len().max(1) as f32;
+        let x = self.playhead as f32 / len * width;
+        playhead.stroke(
+            &Path::line(
+                Point { x, y: strip.y },
+                Point {
+                    x,
+                    y: bounds.height,
+                },
+            ),
+            playhead_stroke,
+        );
+        playhead.stroke(
+            &Path::rectangle(
+                Point::new(self.scroll as f32 / len * width, strip.y + 1.0),
+                Size::new(
+                    (self.visible() as f32 / len * width).max(2.0),
+                    strip.height - 2.0,
+                ),
+            ),
+            Stroke::default().with_width(1.0).with_color(Color::WHITE),
+        );
+        vec![geometry, overview, playhead.into_geometry()]
     }
 
     fn update(
         &self,
         state: &mut Self::State,
         event: Event,
-        _bounds: Rectangle,
-        _cursor: Cursor,
+        bounds: Rectangle,
+        cursor: Cursor,
     ) -> (event::Status, Option<Message>) {
         match event {
-            Event::Keyboard(iced::keyboard::Event::KeyPressed{key, ..}) => {
-                match key.as_ref() {
-                    Key::Character("+") | Key::Character("=") => {
-                        state.zoom += 1.0;
-                        self.cache.clear();
-                        (event::Status::Captured, None)
-                    },
-                    Key::Character("-") => {
-                        state.zoom -= 1.0;
-                        self.cache.clear();
+            Event::Keyboard(iced::keyboard::Event::ModifiersChanged(modifiers)) => {
+                state.modifiers = modifiers;
+                (event::Status::Ignored, None)
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let position = match cursor.position_in(bounds) {
+                    Some(position) => position,
+                    None => return (event::Status::Ignored, None),
+                };
+                if position.y >= main_height(bounds) {
+                    state.overview_drag = true;
+                    let at = position.x / bounds.width;
+                    return (event::Status::Captured, Some(Message::ScrollTo(at)));
+                }
+                let sample = self.sample_at(bounds.width, position.x);
+                let select = state.modifiers.shift();
+                state.drag = Some(Drag {
+                    anchor: sample,
+                    current: sample,
+                    select,
+                    sought: sample,
+                    sought_at: Instant::now(),
+                });
+                // a plain click plays from where it lands straight away
+                (
+                    event::Status::Captured,
+                    (!select).then_some(Message::SeekTo(sample)),
+                )
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Middle)) => {
+                match cursor.position_in(bounds) {
+                    Some(position) => {
+                        state.pan = Some(position.x);
                         (event::Status::Captured, None)
-                    },
-                    _ => (event::Status::Ignored, None),
-
+                    }
+                    None => (event::Status::Ignored, None),
                 }
             }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Middle))
+                if state.pan.is_some() =>
+            {
+                state.pan = None;
+                (event::Status::Captured, None)
+            }
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let position = match cursor.position_in(bounds) {
+                    Some(position) => position,
+                    None => return (event::Status::Ignored, None),
+                };
+                let (x, y) = match delta {
+                    mouse::ScrollDelta::Lines { x, y } => (x, y),
+                    mouse::ScrollDelta::Pixels { x, y } => {
+                        (x / PIXELS_PER_NOTCH, y / PIXELS_PER_NOTCH)
+                    }
+                };
+                // sideways scrolling, or shift with an ordinary wheel, pans
+                let message = if x != 0.0 {
+                    Message::Pan(-x * PAN_STEP)
+                } else if state.modifiers.shift() {
+                    Message::Pan(-y * PAN_STEP)
+                } else {
+                    Message::ZoomAt(y, position.x / bounds.width)
+                };
+                (event::Status::Captured, Some(message))
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) if state.overview_drag => {
+                let at = (position.x - bounds.x) / bounds.width;
+                (event::Status::Captured, Some(Message::ScrollTo(at)))
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) if state.pan.is_some() => {
+                let x = position.x - bounds.x;
+                let moved = x - state.pan.replace(x).unwrap_or(x);
+                (
+                    event::Status::Captured,
+                    Some(Message::Pan(-moved / bounds.width)),
+                )
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) if state.drag.is_some() => {
+                let sample = self.sample_at(bounds.width, position.x - bounds.x);
+                let drag = match &mut state.drag {
+                    Some(drag) => drag,
+                    None => return (event::Status::Ignored, None),
+                };
+                drag.current = sample;
+                if drag.select || drag.sought == sample || drag.sought_at.elapsed() < SCRUB_INTERVAL
+                {
+                    return (event::Status::Captured, None);
+                }
+                drag.sought = sample;
+                drag.sought_at = Instant::now();
+                (event::Status::Captured, Some(Message::SeekTo(sample)))
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+                if state.overview_drag =>
+            {
+                state.overview_drag = false;
+                (event::Status::Captured, None)
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                let drag = match state.drag.take() {
+                    Some(drag) => drag,
+                    None => return (event::Status::Ignored, None),
+                };
+                let (start, end) = (drag.anchor.min(drag.current), drag.anchor.max(drag.current));
+                let message = if drag.select && start < end {
+                    Some(Message::LoopRegion(start, end))
+                } else if drag.select || drag.sought != drag.current {
+                    Some(Message::SeekTo(drag.current))
+                } else {
+                    None
+                };
+                (event::Status::Captured, message)
+            }
             _ => (event::Status::Ignored, None),
         }
     }
 
     fn mouse_interaction(
         &self,
-        _state: &Self::State,
-        _bounds: Rectangle,
-        _cursor: Cursor,
+        state: &Self::State,
+        bounds: Rectangle,
+        cursor: Cursor,
     ) -> iced::mouse::Interaction {
-        iced::mouse::Interaction::default()
+        if state.pan.is_some() || state.overview_drag {
+            return iced::mouse::Interaction::Grabbing;
+        }
+        match cursor.position_in(bounds) {
+            Some(position) if position.y >= main_height(bounds) => iced::mouse::Interaction::Grab,
+            Some(_) => iced::mouse::Interaction::Crosshair,
+            None => iced::mouse::Interaction::default(),
+        }
     }
 }
 
-impl From<rodio::Decoder<std::io::BufReader<File>>> for WaveForm {
-    fn from(decoder: rodio::Decoder<std::io::BufReader<File>>) -> WaveForm {
-        let number_channels = decoder.channels();
-        let sample_rate = decoder.sample_rate();
-        let all_samples: Vec<i16> = decoder.into_iter().collect();
-        let mut samples: Vec<i16> = Vec::with_capacity(all_samples.len());
-        for arr in all_samples.chunks_exact(number_channels as usize) {
-            samples.push(arr.iter().sum::<i16>() / number_channels as i16);
+/// Sends the signals of the interleaved `samples` of `path` a chunk at a
+/// time, after the cached peaks if there are any, until `chunks` is closed.
+fn send_chunks(
+    path: &std::path::Path,
+    format: Format,
+    expected_len: Option<usize>,
+    mut samples: impl Iterator<Item = f32>,
+    chunks: &UnboundedSender<WaveFormChunk>,
+) {
+    let channels = format.channels.max(1);
+    let count = signal_count(channels);
+    // with cached peaks the samples are only needed close up
+    let mut peaks = match Peaks::load(path).filter(|cached| cached.len() == count) {
+        Some(cached) => {
+            let chunk = WaveFormChunk {
+                path: path.to_owned(),
+                expected_len: cached.first().map(|peaks| peaks.len),
+                format,
+                peaks: Some(Arc::new(cached)),
+                samples: Vec::new(),
+                done: false,
+            };
+            if chunks.unbounded_send(chunk).is_err() {
+                return;
+            }
+            None
         }
-        WaveForm {
+        None => Some(vec![Peaks::new(); count]),
+    };
+    let chunk_len = CHUNK_LEN * channels as usize;
+    let mut interleaved = Vec::with_capacity(chunk_len);
+    loop {
+        interleaved.clear();
+        interleaved.extend(samples.by_ref().take(chunk_len));
+        let samples = split(&interleaved, channels);
+        let done = samples[0].len() < CHUNK_LEN;
+        if let Some(peaks) = &mut peaks {
+            for (peaks, samples) in peaks.iter_mut().zip(&samples) {
+                peaks.extend(samples);
+            }
+        }
+        let chunk = WaveFormChunk {
+            path: path.to_owned(),
+            expected_len,
+            format,
+            peaks: None,
             samples,
-            bits_per_sample: 16,
-            sample_rate,
-            cache: Cache::new(),
+            done,
+        };
+        if chunks.unbounded_send(chunk).is_err() {
+            return;
+        }
+        if done {
+            if let Some(Err(e)) = peaks.map(|peaks| Peaks::save(path, &peaks)) {
+                eprintln!("could not cache the peaks of {}: {}", path.display(), e);
+            }
+            return;
+        }
+    }
+}
+
+/// The magnitude of full scale in `bits` bit integer samples.
+fn full_scale(bits: u16) -> f32 {
+    2f32.powi(bits.clamp(1, 32) as i32 - 1)
+}
+
+/// How many signals `WaveForm::load` makes of a file with `channels`
+/// channels.
+fn signal_count(channels: u16) -> usize {
+    match channels {
+        0 | 1 => 1,
+        2 => 4,
+        n => 1 + n as usize,
+    }
+}
+
+/// Splits interleaved frames into the signals of a `WaveForm`.
+fn split(interleaved: &[f32], channels: u16) -> Vec<Vec<f32>> {
+    let frames = interleaved.len() / channels.max(1) as usize;
+    let mut signals = vec![Vec::with_capacity(frames); signal_count(channels)];
+    for frame in interleaved.chunks_exact(channels.max(1) as usize) {
+        for (i, signal) in signals.iter_mut().enumerate() {
+            signal.push(signal_of(frame, i));
         }
     }
+    signals
+}
+
+/// Signal `signal` of a `WaveForm` at one frame.
+fn signal_of(frame: &[f32], signal: usize) -> f32 {
+    match (signal, frame) {
+        (0, _) => frame.iter().sum::<f32>() / frame.len() as f32,
+        (i, _) if i <= frame.len() => frame[i - 1],
+        (_, [left, right]) => (left - right) / 2.0,
+        _ => 0.0,
+    }
 }
 
-fn mean(list: &[i16]) -> f64 {
-    let sum: i16 = Iterator::sum(list.iter());
-    f64::from(sum) / (list.len() as f64)
+/// Height of the waveform itself, above the overview.
+fn main_height(bounds: Rectangle) -> f32 {
+    (bounds.height - OVERVIEW_HEIGHT).max(bounds.height / 2.0)
 }